@@ -0,0 +1,118 @@
+//! Smoothing of reading series for display.
+//!
+//! These keep the start and period of every reading and only change values.
+
+use crate::{Error, ErrorKind, Reading};
+
+/// Replaces each reading with the mean of it and up to `window - 1` readings
+/// before it.
+///
+/// Readings near the start average over however many readings are available
+/// so a window larger than the input is the running mean. A window of zero is
+/// treated as one, which leaves the values unchanged.
+pub fn rolling_average(readings: &[Reading], window: usize) -> Vec<Reading> {
+    let window = window.max(1);
+
+    readings
+        .iter()
+        .enumerate()
+        .map(|(index, reading)| {
+            // Summing each window afresh avoids drift from a running total.
+            let values = &readings[(index + 1).saturating_sub(window)..=index];
+            let sum: f64 = values.iter().map(|r| f64::from(r.value)).sum();
+
+            Reading {
+                value: (sum / values.len() as f64) as f32,
+                ..reading.clone()
+            }
+        })
+        .collect()
+}
+
+/// Applies exponential smoothing to the readings.
+///
+/// Each value becomes `alpha * value + (1 - alpha) * previous`, starting from
+/// the first reading's value. `alpha` is clamped to between 0 and 1, higher
+/// values follow the original series more closely. A NaN `alpha` is rejected.
+pub fn exponential_smoothing(readings: &[Reading], alpha: f64) -> Result<Vec<Reading>, Error> {
+    if alpha.is_nan() {
+        return Err(Error {
+            kind: ErrorKind::Client,
+            message: "The smoothing factor must be a number".to_string(),
+            source: None,
+        });
+    }
+
+    let alpha = alpha.clamp(0.0, 1.0);
+    let mut previous: Option<f64> = None;
+
+    Ok(readings
+        .iter()
+        .map(|reading| {
+            let value = f64::from(reading.value);
+            let value = match previous {
+                Some(previous) => alpha * value + (1.0 - alpha) * previous,
+                None => value,
+            };
+            previous = Some(value);
+
+            Reading {
+                value: value as f32,
+                ..reading.clone()
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::ReadingPeriod;
+
+    fn readings(values: &[f32]) -> Vec<Reading> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let start = OffsetDateTime::from_unix_timestamp(index as i64 * 1800).unwrap();
+                Reading::new(start, ReadingPeriod::HalfHour, *value)
+            })
+            .collect()
+    }
+
+    fn values(readings: &[Reading]) -> Vec<f32> {
+        readings.iter().map(|reading| reading.value).collect()
+    }
+
+    #[test]
+    fn rolling_average_over_window() {
+        assert_eq!(
+            values(&rolling_average(&readings(&[1.0, 3.0, 5.0, 7.0]), 2)),
+            vec![1.0, 2.0, 4.0, 6.0]
+        );
+    }
+
+    #[test]
+    fn rolling_average_of_zeros_after_large_values() {
+        let mut input = vec![1.0e6, 0.1, 3.3e5];
+        input.extend([0.0; 3]);
+
+        let averaged = rolling_average(&readings(&input), 3);
+        assert_eq!(averaged[5].value, 0.0);
+    }
+
+    #[test]
+    fn smoothing_rejects_nan() {
+        assert!(exponential_smoothing(&readings(&[1.0]), f64::NAN).is_err());
+        assert_eq!(
+            values(&exponential_smoothing(&readings(&[1.0, 3.0]), 0.5).unwrap()),
+            vec![1.0, 2.0]
+        );
+        assert_eq!(
+            values(&exponential_smoothing(&readings(&[1.0, 3.0]), 2.0).unwrap()),
+            vec![1.0, 3.0]
+        );
+    }
+}
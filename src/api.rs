@@ -1,7 +1,7 @@
 //! API request and response structures.
 #![allow(missing_docs)]
 
-use std::{collections::HashMap, fmt};
+use std::{collections::HashMap, fmt, marker::PhantomData};
 
 use serde::{
     de::{self, MapAccess, Visitor},
@@ -10,7 +10,7 @@ use serde::{
 use serde_json::{Map, Value};
 use time::{format_description, OffsetDateTime, PrimitiveDateTime};
 
-use crate::{Error, ErrorKind};
+use crate::{aggregation_for_classifier, AggregationFunction, Error, ErrorKind};
 
 #[derive(Serialize, Debug)]
 pub(super) struct AuthRequest {
@@ -24,6 +24,22 @@ pub(super) struct ErrorResponse {
     pub message: String,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub(super) enum ErrorBody {
+    Nested { error: ErrorResponse },
+    Flat(ErrorResponse),
+}
+
+impl ErrorBody {
+    pub fn message(self) -> String {
+        match self {
+            ErrorBody::Nested { error } => error.message,
+            ErrorBody::Flat(error) => error.message,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub(super) struct InvalidAuthResponse {
@@ -35,7 +51,9 @@ pub(super) struct InvalidAuthResponse {
 pub(super) struct ValidAuthResponse {
     pub valid: bool,
     pub token: String,
-    #[serde(rename = "exp", with = "time::serde::timestamp")]
+    #[serde(default)]
+    pub account_id: Option<String>,
+    #[serde(rename = "exp", deserialize_with = "lenient_timestamp::deserialize")]
     pub expiry: OffsetDateTime,
 }
 
@@ -56,12 +74,14 @@ impl AuthResponse {
                     Err(Error {
                         kind: ErrorKind::NotAuthenticated,
                         message: "Authentication error".to_string(),
+                        source: None,
                     })
                 }
             }
             AuthResponse::Invalid(response) => Err(Error {
                 kind: ErrorKind::NotAuthenticated,
                 message: response.error.message,
+                source: None,
             }),
         }
     }
@@ -77,7 +97,7 @@ pub(super) struct InvalidValidateResponse {
 #[serde(rename_all = "camelCase")]
 pub(super) struct ValidValidateResponse {
     pub valid: bool,
-    #[serde(rename = "exp", with = "time::serde::timestamp")]
+    #[serde(rename = "exp", deserialize_with = "lenient_timestamp::deserialize")]
     pub expiry: OffsetDateTime,
 }
 
@@ -98,17 +118,31 @@ impl ValidateResponse {
                     Err(Error {
                         kind: ErrorKind::NotAuthenticated,
                         message: "Authentication error".to_string(),
+                        source: None,
                     })
                 }
             }
             ValidateResponse::Invalid(response) => Err(Error {
                 kind: ErrorKind::NotAuthenticated,
                 message: response.error.message,
+                source: None,
             }),
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    pub account_id: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub created_at: Option<OffsetDateTime>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ResourceInfo {
@@ -119,14 +153,25 @@ pub struct ResourceInfo {
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct VirtualEntity {
-    #[serde(rename(deserialize = "veId"))]
+    #[serde(rename = "veId")]
     pub id: String,
     pub name: String,
     pub active: bool,
-    #[serde(rename(deserialize = "veTypeId"))]
+    #[serde(rename = "veTypeId")]
     pub type_id: String,
     pub owner_id: String,
     pub resources: Vec<ResourceInfo>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateVirtualEntity {
+    pub name: String,
+    #[serde(rename = "veTypeId")]
+    pub type_id: String,
+    pub resources: Vec<ResourceInfo>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -146,7 +191,7 @@ pub struct Protocol {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct DeviceType {
-    #[serde(rename(deserialize = "deviceTypeId"))]
+    #[serde(rename = "deviceTypeId")]
     pub id: String,
     pub description: Option<String>,
     pub active: bool,
@@ -157,6 +202,8 @@ pub struct DeviceType {
     pub updated_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -177,7 +224,7 @@ pub struct DeviceProtocol {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Device {
-    #[serde(rename(deserialize = "deviceId"))]
+    #[serde(rename = "deviceId")]
     pub id: String,
     pub description: Option<String>,
     pub active: bool,
@@ -193,9 +240,11 @@ pub struct Device {
     pub updated_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct DataSourceResourceTypeInfo {
     #[serde(rename = "type")]
@@ -218,7 +267,28 @@ impl From<String> for DataSourceResourceTypeInfo {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DataSourceUnitInfo {
+    /// The smart meter hardware id.
+    pub shid: Option<String>,
+    /// The raw value when the API returns a plain string instead of an object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl From<String> for DataSourceUnitInfo {
+    fn from(val: String) -> DataSourceUnitInfo {
+        DataSourceUnitInfo {
+            value: Some(val),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Field {
     pub field_name: String,
@@ -226,21 +296,47 @@ pub struct Field {
     pub negative: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Storage {
     #[serde(rename = "type")]
     pub storage_type: String,
     pub sampling: String,
     #[serde(default)]
-    pub start: serde_json::Value,
+    pub start: Option<StorageStart>,
     pub fields: Vec<Field>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// When storage for a resource type began.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum StorageStart {
+    /// An RFC 3339 date.
+    Time(#[serde(with = "time::serde::rfc3339")] OffsetDateTime),
+    /// A date in UTC without an offset, as used in query parameters.
+    Utc(#[serde(with = "glowmarkt_datetime")] OffsetDateTime),
+    /// A unix timestamp in seconds.
+    Timestamp(#[serde(with = "time::serde::timestamp")] OffsetDateTime),
+    /// Any other form, left as it was received.
+    Other(Value),
+}
+
+impl StorageStart {
+    /// The time storage began, if it is in a recognised form.
+    pub fn time(&self) -> Option<OffsetDateTime> {
+        match self {
+            StorageStart::Time(time) | StorageStart::Utc(time) | StorageStart::Timestamp(time) => {
+                Some(*time)
+            }
+            StorageStart::Other(_) => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ResourceType {
-    #[serde(rename(deserialize = "resourceTypeId"))]
+    #[serde(rename = "resourceTypeId")]
     pub id: String,
     pub name: String,
     pub description: Option<String>,
@@ -249,35 +345,96 @@ pub struct ResourceType {
     pub classifier: Option<String>,
     pub base_unit: Option<String>,
     pub data_source_type: String,
-    #[serde(default, deserialize_with = "ds_type_info_deserializer")]
+    #[serde(default, deserialize_with = "string_or_struct")]
     pub data_source_resource_type_info: Option<DataSourceResourceTypeInfo>,
     #[serde(default)]
     pub units: HashMap<String, String>,
     pub storage: Vec<Storage>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Resource {
-    #[serde(rename(deserialize = "resourceId"))]
+    #[serde(rename = "resourceId")]
     pub id: String,
     pub name: String,
     pub description: Option<String>,
     pub label: Option<String>,
     pub active: bool,
-    #[serde(rename(deserialize = "resourceTypeId"))]
+    #[serde(rename = "resourceTypeId")]
     pub type_id: String,
     pub owner_id: String,
     pub classifier: Option<String>,
     pub base_unit: Option<String>,
     pub data_source_type: String,
-    #[serde(default, deserialize_with = "ds_type_info_deserializer")]
+    #[serde(default, deserialize_with = "string_or_struct")]
     pub data_source_resource_type_info: Option<DataSourceResourceTypeInfo>,
-    pub data_source_unit_info: Option<serde_json::Value>,
+    #[serde(default, deserialize_with = "string_or_struct")]
+    pub data_source_unit_info: Option<DataSourceUnitInfo>,
     #[serde(with = "time::serde::rfc3339")]
     pub updated_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl Resource {
+    /// Whether this resource reports cost rather than consumption.
+    ///
+    /// Uses the `isCost` flag of the resource type information when present
+    /// and otherwise checks for a classifier ending in `.cost`.
+    pub fn is_cost(&self) -> bool {
+        match self
+            .data_source_resource_type_info
+            .as_ref()
+            .and_then(|info| info.is_cost)
+        {
+            Some(is_cost) => is_cost,
+            None => self
+                .classifier
+                .as_deref()
+                .is_some_and(|classifier| classifier.ends_with(".cost")),
+        }
+    }
+
+    /// The ISO 4217 code of the currency that a cost resource reports in.
+    ///
+    /// Uses a `currency` field when the API provides one and otherwise infers
+    /// it from the base unit, so `pence` gives `GBP`. Always `None` for
+    /// resources that do not report cost. The values themselves remain in the
+    /// base unit, normally the minor unit of the currency.
+    pub fn currency(&self) -> Option<String> {
+        if !self.is_cost() {
+            return None;
+        }
+
+        if let Some(Value::String(currency)) = self.extra.get("currency") {
+            return Some(currency.to_uppercase());
+        }
+
+        match self.base_unit.as_deref()? {
+            "pence" | "p" | "£" | "GBP" => Some("GBP".to_owned()),
+            "€" | "EUR" => Some("EUR".to_owned()),
+            _ => None,
+        }
+    }
+
+    /// The aggregation functions that make sense for this resource.
+    ///
+    /// Instantaneous measures such as temperature can only be averaged while
+    /// cumulative ones such as consumption and cost can also be summed.
+    pub fn supported_functions(&self) -> Vec<AggregationFunction> {
+        if !self.is_cost()
+            && aggregation_for_classifier(&self.classifier) == AggregationFunction::Average
+        {
+            vec![AggregationFunction::Average]
+        } else {
+            vec![AggregationFunction::Sum, AggregationFunction::Average]
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -341,14 +498,170 @@ pub struct Plan {
     pub source: Option<String>,
 }
 
+impl Plan {
+    /// The unit rate, normally in pence per kWh.
+    pub fn unit_rate(&self) -> Option<f64> {
+        self.detail_value("rate")
+    }
+
+    /// The standing charge, normally in pence per day.
+    pub fn standing_charge(&self) -> Option<f64> {
+        self.detail_value("standing")
+    }
+
+    fn detail_value(&self, key: &str) -> Option<f64> {
+        self.plan_detail
+            .iter()
+            .filter_map(|detail| detail.get(key))
+            .find_map(|value| match value {
+                Value::Number(number) => number.as_f64(),
+                Value::String(string) => string.parse().ok(),
+                _ => None,
+            })
+    }
+}
+
+impl TariffListData {
+    /// When this tariff takes effect.
+    pub fn effective_from(&self) -> Option<PrimitiveDateTime> {
+        self.effective_date.or(self.from)
+    }
+}
+
 type ReadingTuple = (i64, f32);
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ReadingsResponse {
     pub data: Vec<ReadingTuple>,
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct StoreReadingsRequest {
+    pub data: Vec<ReadingTuple>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FirstTimeData {
+    #[serde(with = "time::serde::timestamp")]
+    pub first_ts: OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FirstTimeResponse {
+    pub data: FirstTimeData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LastTimeData {
+    #[serde(with = "time::serde::timestamp")]
+    pub last_ts: OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LastTimeResponse {
+    pub data: LastTimeData,
+}
+
+/// Unix timestamps in either seconds or milliseconds, told apart by their
+/// magnitude.
+pub(crate) mod lenient_timestamp {
+    use serde::{Deserialize, Deserializer};
+    use time::OffsetDateTime;
+
+    /// Timestamps at least this large are taken to be in milliseconds. As
+    /// seconds this would be over 3000 years from now, as milliseconds it is
+    /// early 1973.
+    const MILLISECONDS_FROM: i64 = 100_000_000_000;
+
+    pub fn from_timestamp(timestamp: i64) -> Result<OffsetDateTime, time::error::ComponentRange> {
+        if timestamp.unsigned_abs() >= MILLISECONDS_FROM as u64 {
+            OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128 * 1_000_000)
+        } else {
+            OffsetDateTime::from_unix_timestamp(timestamp)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        from_timestamp(i64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serializes an [`OffsetDateTime`] in the `YYYY-MM-DDTHH:MM:SS` format that
+/// the API uses for query parameters, for use with `#[serde(with)]`.
+///
+/// Dates are always in UTC, as the API expects, and are converted to UTC when
+/// serializing.
+pub mod glowmarkt_datetime {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use time::{format_description, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+    const FORMAT: &str = "[year]-[month]-[day]T[hour]:[minute]:[second]";
+
+    pub fn serialize<S>(datetime: &OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let format = format_description::parse(FORMAT).map_err(serde::ser::Error::custom)?;
+
+        let formatted = datetime
+            .to_offset(UtcOffset::UTC)
+            .format(&format)
+            .map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&formatted)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let format = format_description::parse(FORMAT).map_err(serde::de::Error::custom)?;
+
+        PrimitiveDateTime::parse(&s, &format)
+            .map(PrimitiveDateTime::assume_utc)
+            .map_err(serde::de::Error::custom)
+    }
+
+    /// As [`glowmarkt_datetime`](super::glowmarkt_datetime) for optional dates.
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use time::OffsetDateTime;
+
+        pub fn serialize<S>(
+            datetime: &Option<OffsetDateTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match datetime {
+                Some(datetime) => super::serialize(datetime, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<OffsetDateTime>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] OffsetDateTime);
+
+            let wrapper: Option<Wrapper> = Option::deserialize(deserializer)?;
+            Ok(wrapper.map(|Wrapper(datetime)| datetime))
+        }
+    }
+}
+
 fn deserialize_datetime<'de, D>(deserializer: D) -> Result<PrimitiveDateTime, D::Error>
 where
     D: Deserializer<'de>,
@@ -357,7 +670,7 @@ where
     let format = format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]")
         .map_err(serde::de::Error::custom)?;
 
-    Ok(PrimitiveDateTime::parse(s, &format).map_err(serde::de::Error::custom)?)
+    PrimitiveDateTime::parse(s, &format).map_err(serde::de::Error::custom)
 }
 
 fn serialize_datetime<S>(datetime: &PrimitiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
@@ -383,7 +696,7 @@ where
             .map_err(serde::de::Error::custom)?;
 
         let primitive_dt =
-            PrimitiveDateTime::parse(&s, &format).map_err(serde::de::Error::custom)?;
+            PrimitiveDateTime::parse(s, &format).map_err(serde::de::Error::custom)?;
 
         Ok(Some(primitive_dt))
     } else {
@@ -412,48 +725,57 @@ where
     }
 }
 
-fn ds_type_info_deserializer<'de, D>(
-    deserializer: D,
-) -> Result<Option<DataSourceResourceTypeInfo>, D::Error>
+fn string_or_struct<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
 where
+    T: Deserialize<'de> + From<String>,
     D: Deserializer<'de>,
 {
-    // This is a Visitor that forwards string types to T's `FromStr` impl and
+    // This is a Visitor that forwards string types to T's `From<String>` impl and
     // forwards map types to T's `Deserialize` impl. The `PhantomData` is to
     // keep the compiler from complaining about T being an unused generic type
     // parameter. We need T in order to know the Value type for the Visitor
     // impl.
-    struct StringOrStruct;
+    struct StringOrStruct<T>(PhantomData<fn() -> T>);
 
-    impl<'de> Visitor<'de> for StringOrStruct {
-        type Value = Option<DataSourceResourceTypeInfo>;
+    impl<'de, T> Visitor<'de> for StringOrStruct<T>
+    where
+        T: Deserialize<'de> + From<String>,
+    {
+        type Value = Option<T>;
 
         fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
             formatter.write_str("string or object")
         }
 
-        fn visit_none<E>(self) -> Result<Option<DataSourceResourceTypeInfo>, E>
+        fn visit_none<E>(self) -> Result<Option<T>, E>
         where
             E: de::Error,
         {
             Ok(None)
         }
 
-        fn visit_str<E>(self, value: &str) -> Result<Option<DataSourceResourceTypeInfo>, E>
+        fn visit_unit<E>(self) -> Result<Option<T>, E>
+        where
+            E: de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Option<T>, E>
         where
             E: de::Error,
         {
             Ok(Some(value.to_owned().into()))
         }
 
-        fn visit_string<E>(self, value: String) -> Result<Option<DataSourceResourceTypeInfo>, E>
+        fn visit_string<E>(self, value: String) -> Result<Option<T>, E>
         where
             E: de::Error,
         {
             Ok(Some(value.into()))
         }
 
-        fn visit_map<M>(self, map: M) -> Result<Option<DataSourceResourceTypeInfo>, M::Error>
+        fn visit_map<M>(self, map: M) -> Result<Option<T>, M::Error>
         where
             M: MapAccess<'de>,
         {
@@ -465,5 +787,258 @@ where
         }
     }
 
-    deserializer.deserialize_any(StringOrStruct)
+    deserializer.deserialize_any(StringOrStruct(PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(fields: &str) -> Resource {
+        serde_json::from_str(&format!(
+            r#"{{
+                "resourceId": "r1",
+                "name": "electricity consumption",
+                "active": true,
+                "resourceTypeId": "t1",
+                "ownerId": "o1",
+                "dataSourceType": "DCC",
+                "updatedAt": "2024-01-01T00:00:00Z",
+                "createdAt": "2024-01-01T00:00:00Z"
+                {}
+            }}"#,
+            fields
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn timestamp_in_seconds() {
+        assert_eq!(
+            lenient_timestamp::from_timestamp(1_700_000_000).unwrap(),
+            OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap()
+        );
+    }
+
+    #[test]
+    fn timestamp_in_milliseconds() {
+        assert_eq!(
+            lenient_timestamp::from_timestamp(1_700_000_000_123).unwrap(),
+            OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_000_000).unwrap()
+        );
+    }
+
+    #[test]
+    fn expiry_in_seconds_or_milliseconds() {
+        let seconds: ValidValidateResponse =
+            serde_json::from_str(r#"{"valid": true, "exp": 1700000000}"#).unwrap();
+        let milliseconds: ValidValidateResponse =
+            serde_json::from_str(r#"{"valid": true, "exp": 1700000000000}"#).unwrap();
+
+        assert_eq!(seconds.expiry, milliseconds.expiry);
+    }
+
+    #[test]
+    fn is_cost_prefers_flag() {
+        let flagged = resource(
+            r#", "classifier": "electricity.consumption", "dataSourceResourceTypeInfo": {"isCost": true}"#,
+        );
+        assert!(flagged.is_cost());
+
+        let unflagged = resource(
+            r#", "classifier": "electricity.consumption.cost", "dataSourceResourceTypeInfo": {"isCost": false}"#,
+        );
+        assert!(!unflagged.is_cost());
+    }
+
+    #[test]
+    fn is_cost_falls_back_to_classifier() {
+        assert!(resource(r#", "classifier": "gas.consumption.cost""#).is_cost());
+        assert!(!resource(r#", "classifier": "gas.consumption""#).is_cost());
+        assert!(!resource("").is_cost());
+    }
+
+    #[test]
+    fn data_source_info_null() {
+        let resource =
+            resource(r#", "dataSourceResourceTypeInfo": null, "dataSourceUnitInfo": null"#);
+        assert_eq!(resource.data_source_resource_type_info, None);
+        assert!(resource.data_source_unit_info.is_none());
+    }
+
+    #[test]
+    fn data_source_info_string() {
+        let resource = resource(r#", "dataSourceResourceTypeInfo": "ELEC""#);
+        let info = resource.data_source_resource_type_info.unwrap();
+        assert_eq!(info.data_type.as_deref(), Some("ELEC"));
+        assert_eq!(info.unit, None);
+    }
+
+    #[test]
+    fn data_source_info_object() {
+        let resource = resource(
+            r#", "dataSourceResourceTypeInfo": {"type": "ELEC", "unit": "kWh", "isCost": false}"#,
+        );
+        let info = resource.data_source_resource_type_info.unwrap();
+        assert_eq!(info.data_type.as_deref(), Some("ELEC"));
+        assert_eq!(info.unit.as_deref(), Some("kWh"));
+        assert_eq!(info.is_cost, Some(false));
+    }
+
+    fn round_trip<T: Serialize + de::DeserializeOwned>(value: &T) -> T {
+        serde_json::from_value(serde_json::to_value(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn resource_round_trip() {
+        let original = resource(
+            r#", "dataSourceResourceTypeInfo": {"type": "ELEC", "unit": "kWh"}, "tariff": "economy7""#,
+        );
+        let resource = round_trip(&original);
+        assert_eq!(resource, original);
+        assert_eq!(resource.id, "r1");
+        assert_eq!(resource.type_id, "t1");
+        assert_eq!(resource.extra["tariff"], "economy7");
+        assert_eq!(resource.extra.len(), 1);
+    }
+
+    #[test]
+    fn resource_type_round_trip() {
+        let original: ResourceType = serde_json::from_str(
+            r#"{
+                "resourceTypeId": "t1",
+                "name": "electricity consumption",
+                "active": true,
+                "dataSourceType": "DCC",
+                "units": {"energy": "kWh"},
+                "storage": [],
+                "region": "uk"
+            }"#,
+        )
+        .unwrap();
+        let resource_type = round_trip(&original);
+        assert_eq!(resource_type.id, "t1");
+        assert_eq!(resource_type.name, "electricity consumption");
+        assert_eq!(resource_type.units["energy"], "kWh");
+        assert_eq!(resource_type.extra["region"], "uk");
+        assert_eq!(resource_type.extra.len(), 1);
+    }
+
+    #[test]
+    fn virtual_entity_round_trip() {
+        let original: VirtualEntity = serde_json::from_str(
+            r#"{
+                "veId": "v1",
+                "name": "home",
+                "active": true,
+                "veTypeId": "vt1",
+                "ownerId": "o1",
+                "resources": [{"resourceId": "r1", "resourceTypeId": "t1"}],
+                "postalCode": "AB1 2CD"
+            }"#,
+        )
+        .unwrap();
+        let entity = round_trip(&original);
+        assert_eq!(entity.id, "v1");
+        assert_eq!(entity.type_id, "vt1");
+        assert_eq!(entity.resources[0].resource_id, "r1");
+        assert_eq!(entity.extra["postalCode"], "AB1 2CD");
+        assert_eq!(entity.extra.len(), 1);
+    }
+
+    #[test]
+    fn device_round_trip() {
+        let original: Device = serde_json::from_str(
+            r#"{
+                "deviceId": "d1",
+                "active": true,
+                "hardwareId": "h1",
+                "deviceTypeId": "dt1",
+                "ownerId": "o1",
+                "hardwareIdNames": ["MAC"],
+                "hardwareIds": {"MAC": "h1"},
+                "parentHardwareId": [],
+                "tags": ["smart meter"],
+                "protocol": {
+                    "protocol": "MQTT",
+                    "sensors": [{"protocolId": "p1", "resourceId": "r1", "resourceTypeId": "t1"}]
+                },
+                "updatedAt": "2024-01-01T00:00:00Z",
+                "createdAt": "2024-01-01T00:00:00Z",
+                "firmware": "1.2"
+            }"#,
+        )
+        .unwrap();
+        let device = round_trip(&original);
+        assert_eq!(device.id, "d1");
+        assert_eq!(device.device_type_id, "dt1");
+        assert_eq!(device.hardware_ids["MAC"], "h1");
+        assert_eq!(device.protocol.sensors[0].resource_id, "r1");
+        assert_eq!(device.updated_at, original.updated_at);
+        assert_eq!(device.extra["firmware"], "1.2");
+        assert_eq!(device.extra.len(), 1);
+    }
+
+    #[test]
+    fn device_type_round_trip() {
+        let original: DeviceType = serde_json::from_str(
+            r#"{
+                "deviceTypeId": "dt1",
+                "description": "smart meter",
+                "active": true,
+                "protocol": {
+                    "protocol": "MQTT",
+                    "sensors": [{"protocolId": "p1", "resourceTypeId": "t1"}]
+                },
+                "configuration": {"interval": 30},
+                "updatedAt": "2024-01-01T00:00:00Z",
+                "createdAt": "2024-01-01T00:00:00Z",
+                "vendor": "acme"
+            }"#,
+        )
+        .unwrap();
+        let device_type = round_trip(&original);
+        assert_eq!(device_type.id, "dt1");
+        assert_eq!(device_type.description.as_deref(), Some("smart meter"));
+        assert_eq!(device_type.protocol.sensors[0].resource_type_id, "t1");
+        assert_eq!(device_type.configuration["interval"], 30);
+        assert_eq!(device_type.extra["vendor"], "acme");
+        assert_eq!(device_type.extra.len(), 1);
+    }
+
+    #[test]
+    fn readings_response_round_trip() {
+        let original: ReadingsResponse =
+            serde_json::from_str(r#"{"data": [[1700000000, 0.5], [1700001800, 1.25]]}"#).unwrap();
+        let value = serde_json::to_value(&original).unwrap();
+        assert_eq!(value["data"][1][0], 1_700_001_800);
+        let response: ReadingsResponse = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            response.data,
+            vec![(1_700_000_000, 0.5), (1_700_001_800, 1.25)]
+        );
+    }
+
+    #[test]
+    fn first_and_last_time_round_trip() {
+        let first: FirstTimeResponse =
+            serde_json::from_str(r#"{"data": {"firstTs": 1700000000}}"#).unwrap();
+        let value = serde_json::to_value(&first).unwrap();
+        assert_eq!(value["data"]["firstTs"], 1_700_000_000);
+        let first: FirstTimeResponse = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            first.data.first_ts,
+            OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap()
+        );
+
+        let last: LastTimeResponse =
+            serde_json::from_str(r#"{"data": {"lastTs": 1700001800}}"#).unwrap();
+        let value = serde_json::to_value(&last).unwrap();
+        assert_eq!(value["data"]["lastTs"], 1_700_001_800);
+        let last: LastTimeResponse = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            last.data.last_ts,
+            OffsetDateTime::from_unix_timestamp(1_700_001_800).unwrap()
+        );
+    }
 }
@@ -0,0 +1,180 @@
+//! The HTTP client used to send requests to the API.
+//!
+//! [`GlowmarktApi`](crate::GlowmarktApi) sends all of its requests through a
+//! [`HttpBackend`], normally [`ReqwestBackend`]. Implementing the trait allows
+//! using a different HTTP client or stubbing out the API entirely.
+
+use std::{
+    fmt::{self, Display},
+    future::Future,
+};
+
+use reqwest::{Client, Method};
+
+use crate::{Error, ErrorKind, GlowmarktEndpoint};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The HTTP method of a request.
+pub enum HttpMethod {
+    /// A `GET` request.
+    Get,
+    /// A `POST` request.
+    Post,
+}
+
+impl HttpMethod {
+    /// The name of the method.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+        }
+    }
+}
+
+impl Display for HttpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A request to send to the API.
+pub struct HttpRequest {
+    /// The method of the request.
+    pub method: HttpMethod,
+    /// The URL to request, without any query string.
+    pub url: String,
+    /// The query parameters, which the backend must URL encode.
+    pub query: Vec<(String, String)>,
+    /// The headers to send.
+    pub headers: Vec<(String, String)>,
+    /// The JSON encoded body, if any.
+    pub body: Option<String>,
+}
+
+impl HttpRequest {
+    pub(crate) fn new(method: HttpMethod, url: String) -> Self {
+        Self {
+            method,
+            url,
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    pub(crate) fn header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The value of a header, matched case-insensitively.
+    pub fn header_value(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A response received from the API.
+pub struct HttpResponse {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The body of the response.
+    pub body: String,
+}
+
+/// Sends HTTP requests to the API.
+pub trait HttpBackend: fmt::Debug + Clone + Send + Sync + 'static {
+    /// Sends a request and returns the response, whatever its status code.
+    ///
+    /// Errors should only be returned when no response was received at all.
+    fn send(
+        &self,
+        request: HttpRequest,
+    ) -> impl Future<Output = Result<HttpResponse, Error>> + Send;
+}
+
+#[derive(Debug, Clone)]
+/// The default backend, using a [`reqwest`] client.
+pub struct ReqwestBackend {
+    client: Client,
+    max_response_bytes: Option<usize>,
+}
+
+impl ReqwestBackend {
+    /// Creates a backend for the endpoint.
+    pub fn new(endpoint: &GlowmarktEndpoint) -> Self {
+        Self {
+            client: Client::builder()
+                .gzip(endpoint.compression)
+                .deflate(endpoint.compression)
+                .build()
+                .expect("Failed to create the HTTP client"),
+            max_response_bytes: endpoint.max_response_bytes,
+        }
+    }
+}
+
+impl HttpBackend for ReqwestBackend {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error> {
+        let method = match request.method {
+            HttpMethod::Get => Method::GET,
+            HttpMethod::Post => Method::POST,
+        };
+
+        let mut builder = self.client.request(method, &request.url);
+        if !request.query.is_empty() {
+            builder = builder.query(&request.query);
+        }
+        for (name, value) in &request.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        let mut response = builder.send().await?;
+        let status = response.status().as_u16();
+
+        let limit = match self.max_response_bytes {
+            Some(limit) => limit,
+            None => {
+                let body = response.text().await?;
+                return Ok(HttpResponse { status, body });
+            }
+        };
+
+        let too_large = || Error {
+            kind: ErrorKind::Response,
+            message: format!(
+                "Response from {} is larger than {} bytes",
+                request.url, limit
+            ),
+            source: None,
+        };
+
+        if response
+            .content_length()
+            .is_some_and(|length| length > limit as u64)
+        {
+            return Err(too_large());
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if bytes.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(HttpResponse {
+            status,
+            body: String::from_utf8_lossy(&bytes).into_owned(),
+        })
+    }
+}
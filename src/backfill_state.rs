@@ -0,0 +1,114 @@
+//! Records the progress of a backfill so that it can be resumed.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use glowmarkt::ReadingPeriod;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::ErrorStr;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackfillState {
+    pub resource_id: String,
+    pub period: String,
+    pub output: String,
+    /// The start of the first reading that has not been written.
+    #[serde(with = "time::serde::rfc3339")]
+    pub next: OffsetDateTime,
+}
+
+impl BackfillState {
+    pub fn new(
+        resource_id: &str,
+        period: ReadingPeriod,
+        output: &Path,
+        next: OffsetDateTime,
+    ) -> Self {
+        Self {
+            resource_id: resource_id.to_owned(),
+            period: period.as_iso8601().to_owned(),
+            output: output.display().to_string(),
+            next,
+        }
+    }
+
+    /// Checks that the state was recorded for the same backfill.
+    pub fn check(
+        &self,
+        resource_id: &str,
+        period: ReadingPeriod,
+        output: &Path,
+    ) -> Result<(), String> {
+        let expected = Self::new(resource_id, period, output, self.next);
+
+        if self.resource_id != expected.resource_id
+            || self.period != expected.period
+            || self.output != expected.output
+        {
+            return Err(format!(
+                "The state file is for a backfill of {} with period {} to {}, not this one.",
+                self.resource_id, self.period, self.output
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads the state, if the file exists.
+pub fn load(path: &Path) -> Result<Option<BackfillState>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read_to_string(path).str_err()?;
+    serde_json::from_str(&data)
+        .map(Some)
+        .map_err(|e| format!("Unable to parse the state file {}: {}", path.display(), e))
+}
+
+/// Stores the state, replacing the file atomically so an interruption never
+/// leaves it half written.
+pub fn store(path: &Path, state: &BackfillState) -> Result<(), String> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+
+    fs::write(&temp, serde_json::to_string(state).str_err()?).str_err()?;
+    fs::rename(&temp, path).str_err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_leaves_similarly_named_files() {
+        let name = format!("glowmarkt-backfill-{}", std::process::id());
+        let path = std::env::temp_dir().join(format!("{}.json", name));
+        let other = std::env::temp_dir().join(format!("{}.tmp", name));
+        fs::write(&other, "unrelated").unwrap();
+        let state = BackfillState::new(
+            "r1",
+            ReadingPeriod::HalfHour,
+            Path::new("readings.csv"),
+            OffsetDateTime::UNIX_EPOCH,
+        );
+
+        store(&path, &state).unwrap();
+        let loaded = load(&path);
+        let unrelated = fs::read_to_string(&other);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&other).unwrap();
+
+        let loaded = loaded.unwrap().unwrap();
+        assert_eq!(loaded.resource_id, "r1");
+        assert_eq!(loaded.next, OffsetDateTime::UNIX_EPOCH);
+        assert_eq!(unrelated.unwrap(), "unrelated");
+    }
+}
@@ -0,0 +1,43 @@
+//! An in-memory cache of API responses.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (Instant, String)>>>,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Default::default(),
+        }
+    }
+
+    /// Retrieves a response if it was stored less than the TTL ago.
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(key) {
+            Some((stored, body)) if stored.elapsed() < self.ttl => Some(body.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, key: String, body: String) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key, (Instant::now(), body));
+    }
+}
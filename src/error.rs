@@ -1,9 +1,14 @@
 //! A basic error container.
 
-use std::fmt::{self, Display};
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display},
+};
 
 use reqwest::StatusCode;
 
+use crate::api::ErrorBody;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// The type of an error.
 pub enum ErrorKind {
@@ -19,14 +24,100 @@ pub enum ErrorKind {
     Server,
     /// An error decoding the API response.
     Response,
+    /// The API returned no data.
+    NoData,
+    /// The requested date range is larger than the API accepts.
+    ///
+    /// Splitting the request with [`split_periods`](crate::split_periods)
+    /// avoids this.
+    RangeTooLarge,
+    /// The time budget of the endpoint was spent.
+    ///
+    /// See [`GlowmarktEndpoint::with_time_budget`](crate::GlowmarktEndpoint::with_time_budget).
+    DeadlineExceeded,
+    /// The response ended before a complete JSON document was received,
+    /// normally because the connection dropped.
+    TruncatedResponse,
 }
 
 /// A fairly generic error container.
+#[derive(Debug)]
 pub struct Error {
     /// The type of this error.
     pub kind: ErrorKind,
     /// A description of this error.
     pub message: String,
+    /// The underlying error, if any.
+    ///
+    /// Available through [`std::error::Error::source`].
+    pub source: Option<Box<dyn StdError + Send + Sync>>,
+}
+
+impl Error {
+    fn reqwest_error(&self) -> Option<&reqwest::Error> {
+        self.source
+            .as_ref()
+            .and_then(|source| source.downcast_ref::<reqwest::Error>())
+    }
+
+    /// Whether the request timed out.
+    pub fn is_timeout(&self) -> bool {
+        self.reqwest_error().is_some_and(|e| e.is_timeout())
+    }
+
+    /// Whether connecting to the API failed.
+    pub fn is_connect(&self) -> bool {
+        self.reqwest_error().is_some_and(|e| e.is_connect())
+    }
+
+    /// Whether the request was rejected due to a failure to authenticate.
+    pub fn is_auth(&self) -> bool {
+        self.kind == ErrorKind::NotAuthenticated
+    }
+
+    /// Whether the same request might succeed if tried again.
+    ///
+    /// True for network errors, including truncated responses, and errors on
+    /// the server.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::Network | ErrorKind::Server | ErrorKind::TruncatedResponse
+        )
+    }
+
+    /// Converts a failure to parse the body of a response, telling a body that
+    /// was cut short apart from one that is empty or complete but unexpected.
+    pub(crate) fn from_parse(error: serde_json::Error, url: &str, body: &str) -> Self {
+        if body.trim().is_empty() {
+            Self {
+                kind: ErrorKind::Response,
+                message: format!("Empty response from {}", url),
+                source: Some(Box::new(error)),
+            }
+        } else if error.is_eof() {
+            Self {
+                kind: ErrorKind::TruncatedResponse,
+                message: format!(
+                    "Response from {} ended after {} bytes: {}",
+                    url,
+                    body.len(),
+                    error
+                ),
+                source: Some(Box::new(error)),
+            }
+        } else {
+            error.into()
+        }
+    }
+
+    /// Prefixes the message with some context.
+    ///
+    /// The kind and source are kept so callers can still match on them.
+    pub fn with_context<C: Display>(mut self, context: C) -> Self {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
 }
 
 pub(crate) fn maybe<T>(result: Result<T, Error>) -> Result<Option<T>, Error> {
@@ -48,31 +139,126 @@ impl Display for Error {
     }
 }
 
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn StdError + 'static))
+    }
+}
+
 impl From<Error> for String {
     fn from(error: Error) -> String {
         format!("{}", error)
     }
 }
 
+fn status_kind(status: StatusCode) -> ErrorKind {
+    if status == StatusCode::NOT_FOUND {
+        ErrorKind::NotFound
+    } else if status == StatusCode::UNAUTHORIZED {
+        ErrorKind::NotAuthenticated
+    } else if status.is_server_error() {
+        ErrorKind::Server
+    } else {
+        ErrorKind::Client
+    }
+}
+
+/// Finds a limit like `10 days` in an error message from the API.
+///
+/// Only a whole number followed by a unit of time is taken as the limit so
+/// numbers within other words, such as the `30` of `PT30M`, are skipped.
+fn find_limit(message: &str) -> Option<String> {
+    const UNITS: [&str; 6] = ["minute", "hour", "day", "week", "month", "year"];
+
+    let words: Vec<&str> = message.split_whitespace().collect();
+
+    words.windows(2).find_map(|pair| {
+        let number = pair[0].trim_start_matches('(');
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let unit = pair[1].trim_end_matches(|c: char| !c.is_alphanumeric());
+        let singular = unit.to_lowercase();
+        let singular = singular.strip_suffix('s').unwrap_or(&singular);
+        UNITS
+            .contains(&singular)
+            .then(|| format!("{} {}", number, unit))
+    })
+}
+
+fn is_range_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+
+    (message.contains("range") || message.contains("period"))
+        && ["exceed", "too large", "too long", "maximum", "max"]
+            .iter()
+            .any(|word| message.contains(word))
+}
+
+impl Error {
+    /// Creates an error for an unsuccessful HTTP status code.
+    ///
+    /// The response body is checked for an error message from the API.
+    pub(crate) fn from_status(status: u16, url: &str, body: &str) -> Self {
+        let status = match StatusCode::from_u16(status) {
+            Ok(status) => status,
+            Err(_) => {
+                return Self {
+                    kind: ErrorKind::Response,
+                    message: format!("Invalid HTTP status {} for url ({})", status, url),
+                    source: None,
+                }
+            }
+        };
+
+        let api_message = serde_json::from_str::<ErrorBody>(body)
+            .ok()
+            .map(ErrorBody::message);
+
+        match api_message {
+            Some(message) if status.is_client_error() && is_range_error(&message) => {
+                let limit = match find_limit(&message) {
+                    Some(limit) => format!(" The API accepts at most {}.", limit),
+                    None => String::new(),
+                };
+
+                Self {
+                    kind: ErrorKind::RangeTooLarge,
+                    message: format!(
+                        "The requested range is too large, split it into smaller requests.{} ({})",
+                        limit, message
+                    ),
+                    source: None,
+                }
+            }
+            Some(message) => Self {
+                kind: status_kind(status),
+                message: format!("{} (HTTP status {} for url ({}))", message, status, url),
+                source: None,
+            },
+            None => Self {
+                kind: status_kind(status),
+                message: format!("HTTP status {} for url ({})", status, url),
+                source: None,
+            },
+        }
+    }
+}
+
 impl From<reqwest::Error> for Error {
     fn from(error: reqwest::Error) -> Self {
-        let kind = if let Some(status) = error.status() {
-            if status == StatusCode::NOT_FOUND {
-                ErrorKind::NotFound
-            } else if status == StatusCode::UNAUTHORIZED {
-                ErrorKind::NotAuthenticated
-            } else if status.is_server_error() {
-                ErrorKind::Server
-            } else {
-                ErrorKind::Client
-            }
-        } else {
-            ErrorKind::Network
+        let kind = match error.status() {
+            Some(status) => status_kind(status),
+            None => ErrorKind::Network,
         };
 
         Self {
             kind,
             message: error.to_string(),
+            source: Some(Box::new(error)),
         }
     }
 }
@@ -82,6 +268,84 @@ impl From<serde_json::Error> for Error {
         Self {
             kind: ErrorKind::Response,
             message: error.to_string(),
+            source: Some(Box::new(error)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(body: &str) -> Error {
+        let error = serde_json::from_str::<serde_json::Value>(body).unwrap_err();
+        Error::from_parse(error, "https://example.com", body)
+    }
+
+    #[test]
+    fn empty_body_is_not_truncated() {
+        let error = parse_error("");
+        assert_eq!(error.kind, ErrorKind::Response);
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn cut_short_body_is_truncated() {
+        let error = parse_error(r#"{"data": [[1700000000, 1.0]"#);
+        assert_eq!(error.kind, ErrorKind::TruncatedResponse);
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn limit_after_period() {
+        assert_eq!(
+            find_limit("Requested period PT30M exceeds the maximum range of 10 days"),
+            Some("10 days".to_string())
+        );
+        assert_eq!(
+            find_limit("Range too large, max 31 days for period PT1H."),
+            Some("31 days".to_string())
+        );
+        assert_eq!(
+            find_limit("Period P1M is limited to a maximum of 1 year."),
+            Some("1 year".to_string())
+        );
+    }
+
+    #[test]
+    fn no_limit_without_unit() {
+        assert_eq!(
+            find_limit("Requested range exceeds the maximum allowed"),
+            None
+        );
+        assert_eq!(find_limit("Error 400 for period PT30M"), None);
+    }
+
+    #[test]
+    fn range_error_from_status() {
+        let error = Error::from_status(
+            400,
+            "https://example.com",
+            r#"{"error": {"message": "Requested period PT30M exceeds the maximum range of 10 days"}}"#,
+        );
+        assert_eq!(error.kind, ErrorKind::RangeTooLarge);
+        assert!(error.message.contains("The API accepts at most 10 days."));
+    }
+
+    #[test]
+    fn context_keeps_kind_and_source() {
+        let source = serde_json::from_str::<u32>("x").unwrap_err();
+        let error = Error {
+            kind: ErrorKind::Server,
+            message: "failed".to_string(),
+            source: Some(Box::new(source)),
+        }
+        .with_context("Reading resource r1");
+
+        assert_eq!(error.kind, ErrorKind::Server);
+        assert_eq!(error.message, "Reading resource r1: failed");
+        assert!(StdError::source(&error)
+            .and_then(|source| source.downcast_ref::<serde_json::Error>())
+            .is_some());
+    }
+}
@@ -1,16 +1,25 @@
+//! Conversion of readings to the InfluxDB line protocol or annotated CSV.
+
 use std::{collections::BTreeMap, fmt};
 
-use glowmarkt::{Device, Resource};
 use time::{OffsetDateTime, UtcOffset};
 
+use crate::{Device, Reading, Resource};
+
+/// A single line of InfluxDB line protocol.
 pub struct Measurement {
+    /// The measurement name.
     pub id: String,
+    /// The timestamp in nanoseconds since the unix epoch.
     pub timestamp: i128,
+    /// The tags for this measurement.
     pub tags: BTreeMap<String, String>,
+    /// The field values for this measurement.
     pub fields: BTreeMap<String, f64>,
 }
 
 impl Measurement {
+    /// Creates a measurement with no fields.
     pub fn new(id: &str, timestamp: OffsetDateTime, tags: BTreeMap<String, String>) -> Self {
         Measurement {
             id: id.to_owned(),
@@ -20,6 +29,7 @@ impl Measurement {
         }
     }
 
+    /// Adds a field value, which must be finite.
     pub fn add_field(&mut self, key: &str, value: f64) {
         assert!(value.is_finite());
 
@@ -62,6 +72,125 @@ impl fmt::Display for Measurement {
     }
 }
 
+/// Formats measurements as InfluxDB v2 annotated CSV, as accepted by
+/// `influx write --format csv`.
+///
+/// A new table with its own annotation and header rows is started whenever a
+/// measurement has different tags or fields to the previous one.
+/// [`Self::format_all`] groups measurements to avoid starting more tables
+/// than needed.
+#[derive(Debug, Default)]
+pub struct AnnotatedCsv {
+    columns: Option<Columns>,
+}
+
+/// The tag and field keys of a table.
+type Columns = (Vec<String>, Vec<String>);
+
+impl AnnotatedCsv {
+    /// Creates a formatter that has not yet output any table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn columns(measurement: &Measurement) -> Columns {
+        (
+            measurement.tags.keys().cloned().collect(),
+            measurement.fields.keys().cloned().collect(),
+        )
+    }
+
+    /// Formats measurements as CSV rows, grouping those with the same tags and
+    /// fields into a single table.
+    ///
+    /// Measurements keep their order within a table. Measurements that fit the
+    /// last table output are written first so its header isn't repeated.
+    /// Measurements without any fields are skipped.
+    pub fn format_all<I>(&mut self, measurements: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = Measurement>,
+    {
+        let mut tables: Vec<(Columns, Vec<Measurement>)> = Vec::new();
+        for measurement in measurements {
+            let columns = Self::columns(&measurement);
+            match tables.iter_mut().find(|(table, _)| *table == columns) {
+                Some((_, rows)) => rows.push(measurement),
+                None => tables.push((columns, vec![measurement])),
+            }
+        }
+
+        if let Some(index) = tables
+            .iter()
+            .position(|(table, _)| Some(table) == self.columns.as_ref())
+        {
+            let current = tables.remove(index);
+            tables.insert(0, current);
+        }
+
+        tables
+            .into_iter()
+            .flat_map(|(_, rows)| rows)
+            .filter_map(|measurement| self.format(&measurement))
+            .collect()
+    }
+
+    /// Formats a measurement as a CSV row, preceded by the annotation and
+    /// header rows if a new table is needed.
+    ///
+    /// Returns `None` for a measurement without any fields, which has no row.
+    pub fn format(&mut self, measurement: &Measurement) -> Option<String> {
+        if measurement.fields.is_empty() {
+            return None;
+        }
+
+        let columns = Self::columns(measurement);
+
+        let mut output = String::new();
+        if self.columns.as_ref() != Some(&columns) {
+            if self.columns.is_some() {
+                output.push('\n');
+            }
+
+            let (tags, fields) = &columns;
+            let datatypes = std::iter::once("measurement")
+                .chain(tags.iter().map(|_| "tag"))
+                .chain(fields.iter().map(|_| "double"))
+                .chain(std::iter::once("dateTime:number"))
+                .collect::<Vec<_>>();
+            let headers = std::iter::once("_measurement")
+                .chain(tags.iter().map(String::as_str))
+                .chain(fields.iter().map(String::as_str))
+                .chain(std::iter::once("_time"))
+                .map(csv_escape)
+                .collect::<Vec<_>>();
+
+            output.push_str(&format!("#datatype {}\n", datatypes.join(",")));
+            output.push_str(&headers.join(","));
+            output.push('\n');
+
+            self.columns = Some(columns);
+        }
+
+        let row = std::iter::once(csv_escape(&measurement.id))
+            .chain(measurement.tags.values().map(|value| csv_escape(value)))
+            .chain(measurement.fields.values().map(|value| value.to_string()))
+            .chain(std::iter::once(measurement.timestamp.to_string()))
+            .collect::<Vec<_>>();
+        output.push_str(&row.join(","));
+
+        Some(output)
+    }
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Adds tags describing a device.
 pub fn add_tags_for_device(tags: &mut BTreeMap<String, String>, device: &Device) {
     tags.insert("device-id".to_string(), device.id.clone());
     if let Some(ref description) = device.description {
@@ -74,6 +203,7 @@ pub fn add_tags_for_device(tags: &mut BTreeMap<String, String>, device: &Device)
     }
 }
 
+/// Adds tags describing a resource.
 pub fn add_tags_for_resource(tags: &mut BTreeMap<String, String>, resource: &Resource) {
     tags.insert("resource-id".to_string(), resource.id.clone());
     tags.insert("resource".to_string(), resource.name.clone());
@@ -94,14 +224,119 @@ pub fn add_tags_for_resource(tags: &mut BTreeMap<String, String>, resource: &Res
     }
 }
 
+/// The field name to use for a resource with the given classifier.
 pub fn field_for_classifier(classifier: &Option<String>) -> &str {
     if let Some(classifier) = classifier {
-        classifier.split('.').last().unwrap()
+        classifier.split('.').next_back().unwrap()
     } else {
         "value"
     }
 }
 
+/// Converts a resource's readings into measurements with the given name.
+///
+/// The resource's tags (see [`add_tags_for_resource`]) are added to the given
+/// tags for each measurement.
+pub fn measurements_for_readings(
+    id: &str,
+    resource: &Resource,
+    tags: &BTreeMap<String, String>,
+    readings: &[Reading],
+) -> Vec<Measurement> {
+    let mut tags = tags.clone();
+    add_tags_for_resource(&mut tags, resource);
+
+    readings
+        .iter()
+        .map(|reading| {
+            let mut measurement = Measurement::new(id, reading.start, tags.clone());
+            measurement.add_field(
+                field_for_classifier(&resource.classifier),
+                reading.value as f64,
+            );
+            measurement
+        })
+        .collect()
+}
+
+/// Removes the trailing timestamps whose measurements are all zero, returning
+/// them.
+///
+/// Timestamps are removed starting from the latest and stopping at the first
+/// timestamp with any non-zero field, zero values earlier than that are kept.
+/// The API reports zero for periods it has not yet received data for so this
+/// avoids submitting readings that will later change.
+pub fn strip_trailing_zero_readings(
+    measurements: &mut BTreeMap<i128, Vec<Measurement>>,
+) -> BTreeMap<i128, Vec<Measurement>> {
+    let mut stripped = BTreeMap::new();
+
+    while let Some(entry) = measurements.last_entry() {
+        if entry
+            .get()
+            .iter()
+            .all(|m| m.fields.values().all(|v| *v == 0.0))
+        {
+            let (timestamp, zeros) = entry.remove_entry();
+            stripped.insert(timestamp, zeros);
+        } else {
+            break;
+        }
+    }
+
+    stripped
+}
+
 fn escape(tag: &str) -> String {
     tag.replace(' ', "\\ ").replace(',', "\\,")
 }
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    fn measurement(timestamp: i64, field: &str) -> Measurement {
+        let mut tags = BTreeMap::new();
+        tags.insert("resource-id".to_string(), field.to_string());
+
+        let mut measurement = Measurement::new(
+            "glowmarkt",
+            OffsetDateTime::from_unix_timestamp(timestamp).unwrap(),
+            tags,
+        );
+        measurement.add_field(field, 1.0);
+        measurement
+    }
+
+    #[test]
+    fn csv_groups_tables_by_columns() {
+        let mut csv = AnnotatedCsv::new();
+        let output = csv
+            .format_all(vec![
+                measurement(0, "consumption"),
+                measurement(0, "cost"),
+                measurement(1800, "consumption"),
+                measurement(1800, "cost"),
+            ])
+            .join("\n");
+
+        assert_eq!(output.matches("#datatype").count(), 2);
+
+        // A following batch continues the last table.
+        let output = csv.format_all(vec![measurement(3600, "cost")]).join("\n");
+        assert!(!output.contains("#datatype"));
+    }
+
+    #[test]
+    fn csv_skips_measurements_without_fields() {
+        let mut csv = AnnotatedCsv::new();
+        let empty = Measurement::new("glowmarkt", OffsetDateTime::UNIX_EPOCH, BTreeMap::new());
+        assert_eq!(csv.format(&empty), None);
+
+        let output = csv.format_all(vec![empty, measurement(0, "consumption")]);
+        assert_eq!(output.len(), 1);
+        assert!(output[0].ends_with("glowmarkt,consumption,1,0"));
+    }
+}
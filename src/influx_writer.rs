@@ -0,0 +1,173 @@
+//! Writes line protocol directly to an InfluxDB v2 server.
+
+use std::{future::Future, time::Duration};
+
+use reqwest::{Client, StatusCode};
+
+/// The most lines to send in a single request.
+const BATCH_SIZE: usize = 5000;
+/// How many times to try sending a batch before giving up.
+const ATTEMPTS: u32 = 3;
+
+/// Waits between attempts, replaceable so retries can be tested without real
+/// delays.
+pub trait Sleeper {
+    fn sleep(&self, delay: Duration) -> impl Future<Output = ()>;
+}
+
+/// Sleeps using the tokio timer.
+pub struct TokioSleeper;
+
+impl Sleeper for TokioSleeper {
+    async fn sleep(&self, delay: Duration) {
+        tokio::time::sleep(delay).await
+    }
+}
+
+/// Calls `send` until it succeeds, fails with an error that isn't worth
+/// retrying or has been tried [`ATTEMPTS`] times, backing off for 1s, 2s, …
+/// between attempts.
+async fn with_retries<S, F, Fut>(sleeper: &S, mut send: F) -> Result<(), String>
+where
+    S: Sleeper,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), (bool, String)>>,
+{
+    let mut attempt = 1;
+    loop {
+        match send().await {
+            Ok(()) => return Ok(()),
+            Err((true, message)) if attempt < ATTEMPTS => {
+                let delay = Duration::from_secs(1 << (attempt - 1));
+                log::warn!("{}, retrying in {:?}", message, delay);
+                sleeper.sleep(delay).await;
+                attempt += 1;
+            }
+            Err((_, message)) => return Err(message),
+        }
+    }
+}
+
+pub struct InfluxWriter<S: Sleeper = TokioSleeper> {
+    client: Client,
+    url: String,
+    token: Option<String>,
+    org: Option<String>,
+    bucket: String,
+    sleeper: S,
+}
+
+impl InfluxWriter {
+    pub fn new(url: &str, token: Option<String>, org: Option<String>, bucket: String) -> Self {
+        Self {
+            client: Client::new(),
+            url: format!("{}/api/v2/write", url.trim_end_matches('/')),
+            token,
+            org,
+            bucket,
+            sleeper: TokioSleeper,
+        }
+    }
+}
+
+impl<S: Sleeper> InfluxWriter<S> {
+    /// Writes the lines in batches, retrying batches that fail with a network
+    /// or server error.
+    pub async fn write(&self, lines: &[String]) -> Result<(), String> {
+        for batch in lines.chunks(BATCH_SIZE) {
+            let body = batch.join("\n");
+            with_retries(&self.sleeper, || self.send(&body)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a single batch, returning whether a failure is worth retrying.
+    async fn send(&self, body: &str) -> Result<(), (bool, String)> {
+        let mut query = vec![("bucket", self.bucket.as_str()), ("precision", "ns")];
+        if let Some(ref org) = self.org {
+            query.push(("org", org));
+        }
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            .query(&query)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body.to_owned());
+        if let Some(ref token) = self.token {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| (true, format!("Failed to write to InfluxDB: {}", e)))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let retryable = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+        let body = response.text().await.unwrap_or_default();
+        Err((
+            retryable,
+            format!("InfluxDB rejected the write with {}: {}", status, body),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    /// Records the delays instead of waiting.
+    #[derive(Default)]
+    struct RecordingSleeper {
+        delays: RefCell<Vec<Duration>>,
+    }
+
+    impl Sleeper for RecordingSleeper {
+        async fn sleep(&self, delay: Duration) {
+            self.delays.borrow_mut().push(delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_three_times_then_gives_up() {
+        let sleeper = RecordingSleeper::default();
+        let calls = Cell::new(0);
+
+        let result = with_retries(&sleeper, || async {
+            calls.set(calls.get() + 1);
+            Err((true, format!("failure {}", calls.get())))
+        })
+        .await;
+
+        assert_eq!(result, Err("failure 3".to_string()));
+        assert_eq!(calls.get(), 3);
+        assert_eq!(
+            *sleeper.delays.borrow(),
+            vec![Duration::from_secs(1), Duration::from_secs(2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_failures() {
+        let sleeper = RecordingSleeper::default();
+        let calls = Cell::new(0);
+
+        let result = with_retries(&sleeper, || async {
+            calls.set(calls.get() + 1);
+            Err((false, "rejected".to_string()))
+        })
+        .await;
+
+        assert_eq!(result, Err("rejected".to_string()));
+        assert_eq!(calls.get(), 1);
+        assert!(sleeper.delays.borrow().is_empty());
+    }
+}
@@ -3,19 +3,37 @@
 //! Developed based on <https://bitbucket.org/ijosh/brightglowmarkt/src/master/>
 #![warn(missing_docs)]
 
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::{self, Display},
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
+};
 
 use api::{TariffData, TariffListData};
+use backend::{HttpMethod, HttpRequest};
+use cache::ResponseCache;
 use error::maybe;
-use reqwest::{Client, RequestBuilder};
-use serde::{de::DeserializeOwned, Serialize};
+use futures::{future::join_all, stream, Stream, StreamExt, TryStreamExt};
+use rate_limit::RateLimiter;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use time::format_description::well_known::Rfc3339;
-use time::{Duration, Month, OffsetDateTime, UtcOffset};
+use time::{Date, Duration, Month, OffsetDateTime, Time, UtcOffset};
 
+pub mod analytics;
 pub mod api;
+pub mod backend;
+mod cache;
 pub mod error;
-
-pub use api::{Device, DeviceType, Resource, ResourceType, VirtualEntity};
+pub mod influx;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+mod rate_limit;
+
+pub use api::{
+    Account, CreateVirtualEntity, Device, DeviceType, Resource, ResourceType, VirtualEntity,
+};
+pub use backend::{HttpBackend, ReqwestBackend};
 pub use error::{Error, ErrorKind};
 
 /// The default API endpoint.
@@ -23,22 +41,48 @@ pub const BASE_URL: &str = "https://api.glowmarkt.com/api/v0-1";
 /// The default application ID to use when communicating with the API.
 pub const APPLICATION_ID: &str = "b0f1b774-a586-4f72-9edd-27ead8aa7a8d";
 
-fn iso(dt: OffsetDateTime) -> String {
-    format!(
-        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
-        dt.year(),
-        dt.month() as u8,
-        dt.day(),
-        dt.hour(),
-        dt.minute(),
-        dt.second()
-    )
+/// The default maximum number of requests per second sent to the API.
+pub const DEFAULT_RATE_LIMIT: f64 = 5.0;
+
+/// The default number of chunks of a long range of readings requested at
+/// once.
+pub const DEFAULT_CHUNK_CONCURRENCY: usize = 4;
+
+/// The default `User-Agent` header sent to the API.
+pub const USER_AGENT: &str = concat!("glowmarkt-rs/", env!("CARGO_PKG_VERSION"));
+
+/// Formatting of dates as the API expects them.
+trait ApiTime {
+    /// Formats the date in UTC, without an offset.
+    ///
+    /// The date is converted to UTC first, so dates in any offset give the
+    /// correct instant.
+    fn api_time(&self) -> String;
+}
+
+impl ApiTime for OffsetDateTime {
+    fn api_time(&self) -> String {
+        let dt = self.to_offset(UtcOffset::UTC);
+        let sign = if dt.year() < 0 { "-" } else { "" };
+
+        format!(
+            "{}{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            sign,
+            dt.year().unsigned_abs(),
+            dt.month() as u8,
+            dt.day(),
+            dt.hour(),
+            dt.minute(),
+            dt.second()
+        )
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
 /// The time window for each reading.
 pub enum ReadingPeriod {
     /// 30 minutes.
+    #[default]
     HalfHour,
     /// 1 hour.
     Hour,
@@ -52,6 +96,46 @@ pub enum ReadingPeriod {
     Year,
 }
 
+impl ReadingPeriod {
+    /// The ISO-8601 duration used for the period in the API.
+    pub fn as_iso8601(&self) -> &'static str {
+        match self {
+            ReadingPeriod::HalfHour => "PT30M",
+            ReadingPeriod::Hour => "PT1H",
+            ReadingPeriod::Day => "P1D",
+            ReadingPeriod::Week => "P1W",
+            ReadingPeriod::Month => "P1M",
+            ReadingPeriod::Year => "P1Y",
+        }
+    }
+
+    fn from_iso8601(duration: &str) -> Option<Self> {
+        [
+            ReadingPeriod::HalfHour,
+            ReadingPeriod::Hour,
+            ReadingPeriod::Day,
+            ReadingPeriod::Week,
+            ReadingPeriod::Month,
+            ReadingPeriod::Year,
+        ]
+        .into_iter()
+        .find(|period| period.as_iso8601() == duration)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// What to do when readings are requested at a finer period than a resource
+/// stores.
+pub enum SamplingCheck {
+    /// Don't check the resource's sampling.
+    #[default]
+    Off,
+    /// Log a warning.
+    Warn,
+    /// Fail the request.
+    Error,
+}
+
 fn clear_seconds(date: OffsetDateTime) -> OffsetDateTime {
     date.replace_second(0)
         .unwrap()
@@ -63,7 +147,10 @@ fn clear_seconds(date: OffsetDateTime) -> OffsetDateTime {
         .unwrap()
 }
 
-/// Attempts to align the given date to the start of a reading period.
+/// Aligns the given date to the start of a reading period.
+///
+/// Days start at midnight, weeks on Monday, months on the first day of the
+/// month and years on the first of January, all in the date's own offset.
 pub fn align_to_period(date: OffsetDateTime, period: ReadingPeriod) -> OffsetDateTime {
     match period {
         ReadingPeriod::HalfHour => {
@@ -74,12 +161,107 @@ pub fn align_to_period(date: OffsetDateTime, period: ReadingPeriod) -> OffsetDat
             }
         }
         ReadingPeriod::Hour => clear_seconds(date).replace_minute(0).unwrap(),
-        _ => panic!(
-            "Aligning to anything other than half-hour and hour periods is currently unsupported."
-        ),
+        ReadingPeriod::Day => date.replace_time(Time::MIDNIGHT),
+        ReadingPeriod::Week => {
+            date.replace_time(Time::MIDNIGHT)
+                - Duration::days(date.weekday().number_days_from_monday() as i64)
+        }
+        ReadingPeriod::Month => date.replace_time(Time::MIDNIGHT).replace_day(1).unwrap(),
+        ReadingPeriod::Year => date
+            .replace_time(Time::MIDNIGHT)
+            .replace_day(1)
+            .unwrap()
+            .replace_month(Month::January)
+            .unwrap(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The function used to combine values into a single reading.
+pub enum AggregationFunction {
+    /// The total of the values.
+    Sum,
+    /// The mean of the values.
+    Average,
+}
+
+impl AggregationFunction {
+    /// The name of the function in the API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregationFunction::Sum => "sum",
+            AggregationFunction::Average => "avg",
+        }
+    }
+
+    /// Combines a set of values into a single value.
+    ///
+    /// Returns zero for an empty set of values.
+    pub fn aggregate(&self, values: &[f32]) -> f32 {
+        if values.is_empty() {
+            return 0.0;
+        }
+
+        let sum: f32 = values.iter().sum();
+        match self {
+            AggregationFunction::Sum => sum,
+            AggregationFunction::Average => sum / values.len() as f32,
+        }
     }
 }
 
+/// The function to use when requesting readings for a resource with the given
+/// classifier.
+///
+/// Instantaneous measures such as temperature and humidity are averaged,
+/// everything else, such as consumption and cost, is summed.
+pub fn aggregation_for_classifier(classifier: &Option<String>) -> AggregationFunction {
+    let is_instantaneous = classifier
+        .as_deref()
+        .map(|classifier| {
+            classifier
+                .split('.')
+                .any(|part| matches!(part, "temperature" | "humidity"))
+        })
+        .unwrap_or(false);
+
+    if is_instantaneous {
+        AggregationFunction::Average
+    } else {
+        AggregationFunction::Sum
+    }
+}
+
+/// Combines readings into readings of a longer period.
+///
+/// Readings are bucketed by the start of the target period that they fall in
+/// (see [`align_to_period`]) so buckets at the edges of the range may only be
+/// partially filled, they are aggregated from the readings that are present.
+/// The returned readings are sorted by their start time.
+pub fn downsample(
+    readings: &[Reading],
+    to: ReadingPeriod,
+    func: AggregationFunction,
+) -> Vec<Reading> {
+    let mut buckets: BTreeMap<OffsetDateTime, Vec<f32>> = BTreeMap::new();
+
+    for reading in readings {
+        buckets
+            .entry(align_to_period(reading.start, to))
+            .or_default()
+            .push(reading.value);
+    }
+
+    buckets
+        .into_iter()
+        .map(|(start, values)| Reading {
+            start,
+            period: to,
+            value: func.aggregate(&values),
+        })
+        .collect()
+}
+
 fn max_days_for_period(period: ReadingPeriod) -> i64 {
     match period {
         ReadingPeriod::HalfHour => 10,
@@ -97,21 +279,66 @@ fn increase_by_period(date: OffsetDateTime, period: ReadingPeriod) -> OffsetDate
         ReadingPeriod::Hour => Duration::hours(1),
         ReadingPeriod::Day => Duration::days(1),
         ReadingPeriod::Week => Duration::days(7),
-        ReadingPeriod::Month => {
-            let month = date.month();
-            return if month == Month::December {
-                date.replace_month(Month::January).unwrap()
-            } else {
-                date.replace_month(Month::try_from(month as u8 + 1).unwrap())
-                    .unwrap()
-            };
-        }
-        ReadingPeriod::Year => return date.replace_year(date.year() + 1).unwrap(),
+        ReadingPeriod::Month => return add_months(date, 1),
+        ReadingPeriod::Year => return add_months(date, 12),
     };
 
     date + duration
 }
 
+/// Moves a date forward by a number of months, keeping the time of day.
+///
+/// The day is clamped to the length of the target month, so a month after
+/// January 31st is the last day of February.
+fn add_months(date: OffsetDateTime, months: u32) -> OffsetDateTime {
+    let index = date.year() * 12 + i32::from(date.month() as u8 - 1) + months as i32;
+    let year = index.div_euclid(12);
+    let month = Month::try_from(index.rem_euclid(12) as u8 + 1).unwrap();
+    let day = date.day().min(time::util::days_in_year_month(year, month));
+
+    date.replace_date(Date::from_calendar_date(year, month, day).unwrap())
+}
+
+/// Removes the trailing readings with a value of zero.
+///
+/// Readings are removed starting from the end and stopping at the first
+/// non-zero reading, zero readings before that are kept. The API reports zero
+/// for periods it has not yet received data for.
+pub fn trim_trailing_zeros(readings: &mut Vec<Reading>) {
+    while readings.last().map(|r| r.value == 0.0).unwrap_or(false) {
+        readings.pop();
+    }
+}
+
+/// Lists the start of every reading period between `start` and `end`.
+///
+/// Periods are aligned as by [`align_to_period`] and a period is included if
+/// its start falls within the range, inclusive of both ends. Lengths of days,
+/// months and years follow the calendar in the offset of `start`.
+///
+/// A fixed [`UtcOffset`] cannot model daylight saving time. Every period is
+/// stepped in the offset of `start`, so across a clock change the periods stay
+/// aligned to midnight in that offset rather than to local midnight.
+pub fn period_starts(
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    period: ReadingPeriod,
+) -> Vec<OffsetDateTime> {
+    let mut starts = Vec::new();
+
+    let mut current = align_to_period(start, period);
+    if current < start {
+        current = increase_by_period(current, period);
+    }
+
+    while current <= end {
+        starts.push(current);
+        current = increase_by_period(current, period);
+    }
+
+    starts
+}
+
 /// Splits a range of readings into a set of ranges that the API will accept.
 pub fn split_periods(
     start: OffsetDateTime,
@@ -142,7 +369,96 @@ trait Identified {
     fn id(&self) -> &str;
 }
 
+/// Records a field on the current tracing span, if tracing is enabled.
+#[cfg(feature = "tracing")]
+fn record_span<V: tracing::Value>(field: &str, value: V) {
+    tracing::Span::current().record(field, value);
+}
+
+#[cfg(not(feature = "tracing"))]
+fn record_span<V>(_field: &str, _value: V) {}
+
+/// Records metrics for a request sent to the API, if metrics are enabled.
+#[cfg(feature = "metrics")]
+fn record_request(method: HttpMethod, elapsed: std::time::Duration, error: Option<ErrorKind>) {
+    metrics::counter!("glowmarkt_requests_total", "method" => method.as_str()).increment(1);
+    metrics::histogram!("glowmarkt_request_duration_seconds", "method" => method.as_str())
+        .record(elapsed.as_secs_f64());
+
+    if let Some(kind) = error {
+        metrics::counter!(
+            "glowmarkt_request_failures_total",
+            "method" => method.as_str(),
+            "kind" => format!("{:?}", kind)
+        )
+        .increment(1);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+fn record_request(_method: HttpMethod, _elapsed: std::time::Duration, _error: Option<ErrorKind>) {}
+
+/// Converts the raw readings returned by the API, keeping only the last value
+/// for each timestamp.
+///
+/// The API occasionally repeats a timestamp, around DST changes for example.
+/// Readings keep the position of the first occurrence of their timestamp.
+fn dedup_readings(resource_id: &str, data: Vec<(i64, f32)>) -> Vec<(OffsetDateTime, f32)> {
+    let mut readings: Vec<(OffsetDateTime, f32)> = Vec::with_capacity(data.len());
+    let mut indices: HashMap<i64, usize> = HashMap::new();
+    for (timestamp, value) in data {
+        match indices.entry(timestamp) {
+            Entry::Occupied(entry) => {
+                log::warn!(
+                    "Duplicate reading returned for {} at {}, keeping the last",
+                    resource_id,
+                    timestamp
+                );
+                readings[*entry.get()].1 = value;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(readings.len());
+                readings.push((
+                    OffsetDateTime::from_unix_timestamp(timestamp).unwrap(),
+                    value,
+                ));
+            }
+        }
+    }
+
+    readings
+}
+
+/// Lists every period starting within the range, in UTC, with the value of
+/// the reading for it or `None` if there isn't one.
+fn fill_gaps(
+    readings: &[(OffsetDateTime, f32)],
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    period: ReadingPeriod,
+) -> Vec<(OffsetDateTime, Option<f64>)> {
+    let values: HashMap<i64, f64> = readings
+        .iter()
+        .map(|(start, value)| (start.unix_timestamp(), f64::from(*value)))
+        .collect();
+
+    period_starts(
+        start.to_offset(UtcOffset::UTC),
+        end.to_offset(UtcOffset::UTC),
+        period,
+    )
+    .into_iter()
+    .map(|start| (start, values.get(&start.unix_timestamp()).copied()))
+    .collect()
+}
+
+fn parse_body<T: DeserializeOwned>(body: &str, url: &str) -> Result<T, Error> {
+    serde_json::from_str::<T>(body).map_err(|e| Error::from_parse(e, url, body))
+}
+
 fn build_map<I: Identified>(list: Vec<I>) -> HashMap<String, I> {
+    record_span("count", list.len());
+
     list.into_iter()
         .map(|v| (v.id().to_owned(), v))
         .collect::<HashMap<String, I>>()
@@ -178,357 +494,2760 @@ impl Identified for api::Resource {
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 /// A meter reading
 pub struct Reading {
     #[serde(with = "time::serde::rfc3339")]
     /// The start time of the period.
     pub start: OffsetDateTime,
     /// The length of the period.
-    #[serde(skip)]
+    ///
+    /// Not serialized, defaults to half an hour when deserializing.
+    #[serde(skip_serializing, default)]
     pub period: ReadingPeriod,
     /// The total usage.
     pub value: f32,
 }
 
-/// The API endpoint.
-///
-/// Normally a non-default endpoint would only be useful for testing purposes.
-#[derive(Debug, Clone)]
-pub struct GlowmarktEndpoint {
-    /// The URL of the API endpoint.
-    pub base_url: String,
-    /// The application ID to use when communicating with the endpoint.
-    pub app_id: String,
-}
-
-impl Default for GlowmarktEndpoint {
-    fn default() -> Self {
+impl Reading {
+    /// Creates a reading.
+    pub fn new(start: OffsetDateTime, period: ReadingPeriod, value: f32) -> Self {
         Self {
-            base_url: BASE_URL.to_string(),
-            app_id: APPLICATION_ID.to_string(),
+            start,
+            period,
+            value,
         }
     }
-}
 
-impl GlowmarktEndpoint {
-    fn url<S: Display>(&self, path: S) -> String {
-        format!("{}/{}", self.base_url, path)
+    /// The end time of the period, which is the start of the next period.
+    ///
+    /// This is calculated as the next period boundary in the calendar rather
+    /// than by adding a fixed duration so it always matches the start of the
+    /// following reading.
+    pub fn end(&self) -> OffsetDateTime {
+        increase_by_period(self.start, self.period)
     }
 
-    async fn api_call<T>(&self, client: &Client, request: RequestBuilder) -> Result<T, Error>
-    where
-        T: DeserializeOwned,
-    {
-        let request = request
-            .header("applicationId", &self.app_id)
-            .header("Content-Type", "application/json")
-            .build()?;
+    /// The length of time the reading covers, from its start to its end.
+    ///
+    /// This varies for monthly and yearly readings. Dividing an energy reading
+    /// by this gives the average power over the period.
+    pub fn duration(&self) -> Duration {
+        self.end() - self.start
+    }
 
-        log::debug!("Sending {} request to {}", request.method(), request.url());
-        let response = client
-            .execute(request)
-            .await?
-            .error_for_status()
-            .map_err(|e| {
-                log::warn!("Received API error: {}", e);
-                e
+    /// The value of this reading converted to `unit` from the unit that the
+    /// resource type reports in.
+    ///
+    /// The source unit is the entry in the resource type's `units` of the same
+    /// kind as `unit`, so `kWh` is converted from its energy unit. It is an
+    /// error if there is no such entry. See [`convert_unit`] for the supported
+    /// units.
+    pub fn value_in(&self, resource_type: &api::ResourceType, unit: &str) -> Result<f64, Error> {
+        let (kind, _) = unit_scale(unit).ok_or_else(|| Error {
+            kind: ErrorKind::Client,
+            message: format!("Unsupported unit {}", unit),
+            source: None,
+        })?;
+        let from = resource_type
+            .units
+            .values()
+            .find(|from| unit_scale(from).is_some_and(|(from_kind, _)| from_kind == kind))
+            .ok_or_else(|| Error {
+                kind: ErrorKind::Client,
+                message: format!(
+                    "Resource type {} has no {} unit to convert to {}",
+                    resource_type.id, kind, unit
+                ),
+                source: None,
             })?;
 
-        let result = response.text().await?;
-        log::trace!("Received: {}", result);
-
-        Ok(serde_json::from_str::<T>(&result)?)
+        convert_unit(f64::from(self.value), from, unit)
     }
 }
 
-struct ApiRequest<'a> {
-    endpoint: &'a GlowmarktEndpoint,
-    client: &'a Client,
-    request: RequestBuilder,
+/// The kind of quantity and scale relative to the smallest unit of the kind.
+fn unit_scale(unit: &str) -> Option<(&'static str, f64)> {
+    match unit {
+        "Wh" => Some(("energy", 1.0)),
+        "kWh" => Some(("energy", 1_000.0)),
+        "MWh" => Some(("energy", 1_000_000.0)),
+        "W" => Some(("power", 1.0)),
+        "kW" => Some(("power", 1_000.0)),
+        "p" | "pence" => Some(("currency", 1.0)),
+        "£" | "GBP" | "pounds" => Some(("currency", 100.0)),
+        _ => None,
+    }
 }
 
-impl<'a> ApiRequest<'a> {
-    async fn request<T: DeserializeOwned>(self) -> Result<T, Error> {
-        self.endpoint.api_call(self.client, self.request).await
+/// Converts a value between units, such as the entries in a
+/// [`ResourceType`]'s `units`.
+///
+/// Energy (`Wh`, `kWh`, `MWh`), power (`W`, `kW`) and currency (`p`, `£`)
+/// units are supported. Converting between unknown units or units of
+/// different kinds is an error.
+pub fn convert_unit(value: f64, from: &str, to: &str) -> Result<f64, Error> {
+    match (unit_scale(from), unit_scale(to)) {
+        (Some((from_kind, from_scale)), Some((to_kind, to_scale))) if from_kind == to_kind => {
+            Ok(value * from_scale / to_scale)
+        }
+        _ => Err(Error {
+            kind: ErrorKind::Client,
+            message: format!("Cannot convert from {} to {}", from, to),
+            source: None,
+        }),
     }
 }
 
-#[derive(Debug, Clone)]
-/// Access to the Glowmarkt API.
-pub struct GlowmarktApi {
-    /// The current JWT token.
-    pub token: String,
-    endpoint: GlowmarktEndpoint,
-    client: Client,
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+/// The changes between two snapshots of resources, by resource id.
+pub struct ResourceDiff {
+    /// Resources that are only in the new snapshot.
+    pub added: BTreeSet<String>,
+    /// Resources that are only in the old snapshot.
+    pub removed: BTreeSet<String>,
+    /// Resources that are in both snapshots but differ in any way, including
+    /// being renamed, changing type or becoming inactive.
+    pub modified: BTreeSet<String>,
 }
 
-impl GlowmarktApi {
-    /// Create with a provided JWT token.
-    pub fn new(token: &str) -> Self {
-        Self {
-            token: token.to_owned(),
-            endpoint: Default::default(),
-            client: Client::new(),
-        }
+impl ResourceDiff {
+    /// Whether the snapshots are identical.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
     }
+}
 
-    /// Authenticates with the default Glowmarkt API endpoint.
-    ///
-    /// Generates a valid JWT token if successful.
-    pub async fn authenticate(username: &str, password: &str) -> Result<GlowmarktApi, Error> {
-        Self::auth(Default::default(), username, password).await
+/// Compares two snapshots of resources, as returned by
+/// [`GlowmarktApi::resources`].
+pub fn diff_resources(
+    old: &HashMap<String, Resource>,
+    new: &HashMap<String, Resource>,
+) -> ResourceDiff {
+    let mut diff = ResourceDiff::default();
+
+    for (id, resource) in new {
+        match old.get(id) {
+            None => {
+                diff.added.insert(id.clone());
+            }
+            Some(previous) if previous != resource => {
+                diff.modified.insert(id.clone());
+            }
+            Some(_) => {}
+        }
     }
 
-    fn get_request<S>(&self, path: S) -> ApiRequest
-    where
-        S: Display,
-    {
-        let request = self
-            .client
-            .get(self.endpoint.url(path))
-            .header("token", &self.token);
+    diff.removed
+        .extend(old.keys().filter(|id| !new.contains_key(*id)).cloned());
+
+    diff
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+/// A resource in an [`AccountTree`].
+pub struct ResourceNode {
+    /// The resource's id.
+    pub resource_id: String,
+    /// The resource's name, if the resource could be found.
+    pub name: Option<String>,
+    /// The classifier of the resource.
+    pub classifier: Option<String>,
+    /// The id of the resource's type.
+    pub resource_type_id: String,
+    /// The name of the resource's type, if the type could be found.
+    pub resource_type: Option<String>,
+    /// The unit of the resource's readings.
+    pub unit: Option<String>,
+}
 
-        ApiRequest {
-            endpoint: &self.endpoint,
-            client: &self.client,
-            request,
+impl ResourceNode {
+    fn new(
+        resource_id: &str,
+        resource_type_id: &str,
+        resources: &HashMap<String, Resource>,
+        resource_types: &HashMap<String, ResourceType>,
+    ) -> Self {
+        let resource = resources.get(resource_id);
+        let resource_type = resource_types.get(resource_type_id);
+
+        Self {
+            resource_id: resource_id.to_owned(),
+            name: resource.map(|resource| resource.name.clone()),
+            classifier: resource
+                .and_then(|resource| resource.classifier.clone())
+                .or_else(|| resource_type.and_then(|ty| ty.classifier.clone())),
+            resource_type_id: resource_type_id.to_owned(),
+            resource_type: resource_type.map(|ty| ty.name.clone()),
+            unit: resource
+                .and_then(|resource| resource.base_unit.clone())
+                .or_else(|| resource_type.and_then(|ty| ty.base_unit.clone())),
         }
     }
+}
 
-    fn query_request<S, T>(&self, path: S, query: &T) -> ApiRequest
-    where
-        S: Display,
-        T: Serialize + ?Sized,
-    {
-        let request = self
-            .client
-            .get(self.endpoint.url(path))
-            .header("token", &self.token)
-            .query(query);
-
-        ApiRequest {
-            endpoint: &self.endpoint,
-            client: &self.client,
-            request,
-        }
-    }
-
-    // fn post_request<S, T>(&self, path: S, data: &T) -> ApiRequest
-    // where
-    //     S: Display,
-    //     T: Serialize,
-    // {
-    //     let request = self
-    //         .client
-    //         .post(self.endpoint.url(path))
-    //         .header("Content-Type", "application/json")
-    //         .header("token", &self.token)
-    //         .json(data);
-
-    //     ApiRequest {
-    //         endpoint: &self.endpoint,
-    //         client: &self.client,
-    //         request,
-    //     }
-    // }
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+/// A virtual entity in an [`AccountTree`].
+pub struct EntityNode {
+    /// The virtual entity's id.
+    pub entity_id: String,
+    /// The virtual entity's name.
+    pub name: String,
+    /// The resources of the virtual entity.
+    pub resources: Vec<ResourceNode>,
 }
 
-/// [User System](https://api.glowmarkt.com/api-docs/v0-1/usersys/usertypes/)
-impl GlowmarktApi {
-    /// Authenticate against a specific endpoint.
-    pub async fn auth(
-        endpoint: GlowmarktEndpoint,
-        username: &str,
-        password: &str,
-    ) -> Result<GlowmarktApi, Error> {
-        let client = Client::new();
-        let request = client.post(endpoint.url("auth")).json(&api::AuthRequest {
-            username: username.to_owned(),
-            password: password.to_owned(),
-        });
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+/// A device in an [`AccountTree`].
+pub struct DeviceNode {
+    /// The device's id.
+    pub device_id: String,
+    /// The device's description.
+    pub description: Option<String>,
+    /// The device's hardware id.
+    pub hardware_id: String,
+    /// The resources of the device's sensors, in the order of the sensors.
+    pub sensors: Vec<ResourceNode>,
+}
 
-        let response = endpoint
-            .api_call::<api::AuthResponse>(&client, request)
-            .await?
-            .validate()?;
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+/// How the virtual entities, devices and resources of an account relate to
+/// each other.
+///
+/// The same resource appears under every virtual entity and device that
+/// refers to it.
+pub struct AccountTree {
+    /// The virtual entities, sorted by name.
+    pub entities: Vec<EntityNode>,
+    /// The devices, sorted by description.
+    pub devices: Vec<DeviceNode>,
+}
+
+/// Builds an [`AccountTree`] from everything retrieved for an account.
+///
+/// Resources and resource types that are referred to but missing from the
+/// maps are still included, with only their ids filled in.
+pub fn build_account_tree(
+    entities: &HashMap<String, VirtualEntity>,
+    devices: &HashMap<String, Device>,
+    resources: &HashMap<String, Resource>,
+    resource_types: &HashMap<String, ResourceType>,
+) -> AccountTree {
+    let mut entities: Vec<EntityNode> = entities
+        .values()
+        .map(|entity| EntityNode {
+            entity_id: entity.id.clone(),
+            name: entity.name.clone(),
+            resources: entity
+                .resources
+                .iter()
+                .map(|info| {
+                    ResourceNode::new(
+                        &info.resource_id,
+                        &info.resource_type_id,
+                        resources,
+                        resource_types,
+                    )
+                })
+                .collect(),
+        })
+        .collect();
+    entities.sort_by(|a, b| (&a.name, &a.entity_id).cmp(&(&b.name, &b.entity_id)));
+
+    let mut devices: Vec<DeviceNode> = devices
+        .values()
+        .map(|device| DeviceNode {
+            device_id: device.id.clone(),
+            description: device.description.clone(),
+            hardware_id: device.hardware_id.clone(),
+            sensors: device
+                .protocol
+                .sensors
+                .iter()
+                .map(|sensor| {
+                    ResourceNode::new(
+                        &sensor.resource_id,
+                        &sensor.resource_type_id,
+                        resources,
+                        resource_types,
+                    )
+                })
+                .collect(),
+        })
+        .collect();
+    devices.sort_by(|a, b| (&a.description, &a.device_id).cmp(&(&b.description, &b.device_id)));
 
-        log::debug!("Authenticated with API until {}", iso(response.expiry));
+    AccountTree { entities, devices }
+}
 
-        Ok(Self {
-            token: response.token,
-            endpoint,
-            client,
+/// Checks that each reading ends exactly where the next one starts.
+///
+/// Returns `false` if there are gaps or overlaps between readings.
+pub fn is_contiguous(readings: &[Reading]) -> bool {
+    readings
+        .windows(2)
+        .all(|pair| pair[0].end() == pair[1].start)
+}
+
+/// Lines readings up with a common set of timestamps, such as those from
+/// [`period_starts`].
+///
+/// Returns one value for each point of the grid, the value of the reading that
+/// starts at exactly that point or `None` if there isn't one. Readings that
+/// don't start on a grid point are ignored.
+///
+/// Both `readings` and `grid` must be sorted by time, otherwise matching
+/// readings may be missed.
+pub fn align_to_grid(readings: &[Reading], grid: &[OffsetDateTime]) -> Vec<Option<f64>> {
+    let mut readings = readings.iter().peekable();
+
+    grid.iter()
+        .map(|point| {
+            while readings.next_if(|reading| reading.start < *point).is_some() {}
+
+            readings
+                .next_if(|reading| reading.start == *point)
+                .map(|reading| f64::from(reading.value))
         })
-    }
+        .collect()
+}
 
-    /// Validates the current token.
-    pub async fn validate(&self) -> Result<bool, Error> {
-        let response = self
-            .get_request("auth")
-            .request::<api::ValidateResponse>()
-            .await
-            .and_then(|r| r.validate())?;
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+/// Aggregate statistics for a set of readings.
+pub struct ReadingSummary {
+    /// The number of readings.
+    pub count: usize,
+    /// The sum of all readings.
+    pub total: f32,
+    /// The smallest reading.
+    pub min: Option<f32>,
+    /// The largest reading.
+    pub max: Option<f32>,
+    /// The mean of the readings.
+    pub mean: Option<f32>,
+}
 
-        log::debug!("Authenticated with API until {}", iso(response.expiry));
+impl ReadingSummary {
+    /// Summarises a set of readings.
+    ///
+    /// Everything but the count and total is `None` when there are no readings.
+    pub fn from_readings(readings: &[Reading]) -> Self {
+        let values = readings.iter().map(|r| r.value);
+        let total: f32 = values.clone().sum();
 
-        Ok(true)
+        Self {
+            count: readings.len(),
+            total,
+            min: values.clone().reduce(f32::min),
+            max: values.reduce(f32::max),
+            mean: if readings.is_empty() {
+                None
+            } else {
+                Some(total / readings.len() as f32)
+            },
+        }
     }
 }
 
-/// [Device Management System](https://api.glowmarkt.com/api-docs/v0-1/dmssys/#/)
-impl GlowmarktApi {
-    /// Retrieves all of the known device types.
-    pub async fn device_types(&self) -> Result<HashMap<String, api::DeviceType>, Error> {
-        self.get_request("devicetype")
-            .request()
-            .await
-            .map(build_map)
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+/// The readings for a resource along with what they measure.
+///
+/// Iterates over the readings in the same way as a `Vec<Reading>`.
+pub struct ResourceReadings {
+    /// The resource the readings are for.
+    pub resource_id: String,
+    /// The classifier of the resource.
+    pub classifier: Option<String>,
+    /// The unit of the readings.
+    pub unit: Option<String>,
+    /// The currency of the readings for a cost resource, see
+    /// [`Resource::currency`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// The readings.
+    pub readings: Vec<Reading>,
+}
+
+impl ResourceReadings {
+    /// An iterator over the readings.
+    pub fn iter(&self) -> std::slice::Iter<'_, Reading> {
+        self.readings.iter()
     }
 
-    /// Retrieves all of the devices registered for an account.
-    pub async fn devices(&self) -> Result<HashMap<String, api::Device>, Error> {
-        self.get_request("device").request().await.map(build_map)
+    /// The number of readings.
+    pub fn len(&self) -> usize {
+        self.readings.len()
     }
 
-    /// Retrieves a single device.
-    pub async fn device(&self, id: &str) -> Result<Option<api::Device>, Error> {
-        match self.get_request(format!("device/{}", id)).request().await {
-            Ok(device) => Ok(Some(device)),
-            Err(error) => {
-                if error.kind == ErrorKind::NotFound {
-                    Ok(None)
-                } else {
-                    Err(error)
-                }
-            }
-        }
+    /// Whether there are no readings.
+    pub fn is_empty(&self) -> bool {
+        self.readings.is_empty()
     }
 }
 
-/// [Virtual Entity System](https://api.glowmarkt.com/api-docs/v0-1/vesys/#/)
-impl GlowmarktApi {
-    /// Retrieves all of the virtual entities registered for an account.
-    pub async fn virtual_entities(&self) -> Result<HashMap<String, api::VirtualEntity>, Error> {
-        self.get_request("virtualentity")
-            .request()
-            .await
-            .map(build_map)
-    }
+impl IntoIterator for ResourceReadings {
+    type Item = Reading;
+    type IntoIter = std::vec::IntoIter<Reading>;
 
-    /// Retrieves a single virtual entity by ID.
-    pub async fn virtual_entity(
-        &self,
-        entity_id: &str,
-    ) -> Result<Option<api::VirtualEntity>, Error> {
-        maybe(
-            self.get_request(format!("virtualentity/{}", entity_id))
-                .request()
-                .await,
-        )
+    fn into_iter(self) -> Self::IntoIter {
+        self.readings.into_iter()
     }
 }
 
-/// [Resource System](https://api.glowmarkt.com/api-docs/v0-1/resourcesys/#/)
-impl GlowmarktApi {
-    /// Retrieves all of the known resource types.
-    pub async fn resource_types(&self) -> Result<HashMap<String, api::ResourceType>, Error> {
-        self.get_request("resourcetype")
-            .request()
-            .await
-            .map(build_map)
-    }
+impl<'a> IntoIterator for &'a ResourceReadings {
+    type Item = &'a Reading;
+    type IntoIter = std::slice::Iter<'a, Reading>;
 
-    /// Retrieves all resources.
-    pub async fn resources(&self) -> Result<HashMap<String, api::Resource>, Error> {
-        self.get_request("resource").request().await.map(build_map)
+    fn into_iter(self) -> Self::IntoIter {
+        self.readings.iter()
     }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+/// A consumption reading with the cost estimated from the tariff.
+pub struct CostedReading {
+    #[serde(flatten)]
+    /// The consumption reading.
+    pub reading: Reading,
+    /// The unit rate applied to the consumption.
+    pub unit_rate: f32,
+    /// The part of the daily standing charge that falls in this period.
+    pub standing_charge: f32,
+    /// The total estimated cost of the period.
+    pub cost: f32,
+}
+
+/// A tariff rate that applies from a point in time.
+struct TariffRate {
+    from: Option<OffsetDateTime>,
+    unit_rate: f32,
+    standing_charge: f32,
+}
+
+impl TariffRate {
+    fn from_plans(from: Option<OffsetDateTime>, plans: &[api::Plan]) -> Option<Self> {
+        let unit_rate = plans.iter().find_map(api::Plan::unit_rate)?;
+        let standing_charge = plans
+            .iter()
+            .find_map(api::Plan::standing_charge)
+            .unwrap_or_default();
+
+        Some(Self {
+            from,
+            unit_rate: unit_rate as f32,
+            standing_charge: standing_charge as f32,
+        })
+    }
+
+    fn cost(&self, reading: Reading) -> CostedReading {
+        let days = reading.duration().as_seconds_f32() / 86_400.0;
+        let standing_charge = self.standing_charge * days;
+
+        CostedReading {
+            unit_rate: self.unit_rate,
+            standing_charge,
+            cost: self.unit_rate * reading.value + standing_charge,
+            reading,
+        }
+    }
+}
+
+/// The API endpoint.
+///
+/// Normally a non-default endpoint would only be useful for testing purposes.
+///
+/// This cannot be built with a struct literal, start from
+/// [`GlowmarktEndpoint::default`] and use the `with_` methods to change it.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct GlowmarktEndpoint {
+    /// The URL of the API endpoint.
+    pub base_url: String,
+    /// The application ID to use when communicating with the endpoint.
+    pub app_id: String,
+    /// Whether to request gzip or deflate compressed responses.
+    ///
+    /// Enabled by default. Readings in particular compress well so this trades
+    /// a little CPU for much less data transfer. Only used by
+    /// [`ReqwestBackend`].
+    pub compression: bool,
+    /// The `User-Agent` header sent with every request.
+    ///
+    /// Defaults to [`USER_AGENT`].
+    pub user_agent: String,
+    /// The largest response body to accept, in bytes.
+    ///
+    /// Unlimited by default. Larger responses are abandoned and return an
+    /// error. Only used by [`ReqwestBackend`].
+    pub max_response_bytes: Option<usize>,
+    cache: Option<ResponseCache>,
+    rate_limit: Option<RateLimiter>,
+    deadline: Option<Instant>,
+}
+
+impl Default for GlowmarktEndpoint {
+    fn default() -> Self {
+        Self {
+            base_url: BASE_URL.to_string(),
+            app_id: APPLICATION_ID.to_string(),
+            compression: true,
+            user_agent: USER_AGENT.to_string(),
+            max_response_bytes: None,
+            cache: None,
+            rate_limit: Some(RateLimiter::new(DEFAULT_RATE_LIMIT)),
+            deadline: None,
+        }
+    }
+}
+
+impl GlowmarktEndpoint {
+    /// Sets the URL of the API endpoint.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Sets the application ID to use when communicating with the endpoint.
+    pub fn with_app_id<S: Into<String>>(mut self, app_id: S) -> Self {
+        self.app_id = app_id.into();
+        self
+    }
+
+    /// Sets whether to request compressed responses.
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the `User-Agent` header sent with every request.
+    pub fn with_user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Sets the largest response body to accept, in bytes.
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.max_response_bytes = Some(max_response_bytes);
+        self
+    }
+
+    /// Enables an in-memory cache of responses.
+    ///
+    /// Responses to `GET` requests are reused for `ttl` after they are
+    /// received. Authentication requests are never cached. The cache is shared
+    /// by clones of this endpoint but keyed by token so different accounts do
+    /// not see each other's responses.
+    pub fn with_cache(mut self, ttl: std::time::Duration) -> Self {
+        self.cache = Some(ResponseCache::new(ttl));
+        self
+    }
+
+    /// Limits requests to the given number per second.
+    ///
+    /// Requests are limited to [`DEFAULT_RATE_LIMIT`] per second by default.
+    /// The limit is shared by clones of this endpoint, and so by every API
+    /// created from them, so concurrent requests are also limited.
+    ///
+    /// Panics if `per_second` is not a positive number.
+    pub fn with_rate_limit(mut self, per_second: f64) -> Self {
+        assert!(per_second > 0.0, "The rate limit must be positive");
+
+        self.rate_limit = Some(RateLimiter::new(per_second));
+        self
+    }
+
+    /// Removes the limit on the rate of requests.
+    ///
+    /// The rate limit is the only source of delays between requests, so with
+    /// it removed and a stub [`HttpBackend`] every request path, including the
+    /// retry after re-authenticating, runs without waiting.
+    pub fn without_rate_limit(mut self) -> Self {
+        self.rate_limit = None;
+        self
+    }
+
+    /// Limits the total time that requests through this endpoint may take.
+    ///
+    /// The budget starts now and is shared by clones of this endpoint, and so
+    /// by every API created from them, which bounds a whole batch operation
+    /// such as [`GlowmarktApi::entity_readings`] rather than each request.
+    /// Once it is spent requests fail with [`ErrorKind::DeadlineExceeded`]
+    /// and a request still in flight at that point is abandoned. Time spent
+    /// waiting for the rate limit and the single retry after re-authenticating
+    /// both count against the budget, there are no other retries.
+    pub fn with_time_budget(mut self, budget: std::time::Duration) -> Self {
+        self.deadline = Some(Instant::now() + budget);
+        self
+    }
+
+    fn check_deadline(&self, method: HttpMethod, url: &str) -> Result<(), Error> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(Error {
+                kind: ErrorKind::DeadlineExceeded,
+                message: format!(
+                    "Time budget spent before sending {} request to {}",
+                    method, url
+                ),
+                source: None,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn url<S: Display>(&self, path: S) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(method = tracing::field::Empty, url = tracing::field::Empty)
+        )
+    )]
+    async fn api_call_raw<B>(&self, backend: &B, request: HttpRequest) -> Result<String, Error>
+    where
+        B: HttpBackend,
+    {
+        let request = request
+            .header("applicationId", &self.app_id)
+            .header("Content-Type", "application/json")
+            .header("User-Agent", &self.user_agent);
+
+        let method = request.method;
+        let url = request.url.clone();
+        record_span("method", method.as_str());
+        record_span("url", url.as_str());
+
+        let cache_key = match self.cache {
+            Some(_) if method == HttpMethod::Get && !url.ends_with("/auth") => Some(format!(
+                "{} {} {:?}",
+                request.header_value("token").unwrap_or_default(),
+                url,
+                request.query
+            )),
+            _ => None,
+        };
+
+        if let (Some(cache), Some(key)) = (&self.cache, &cache_key) {
+            if let Some(result) = cache.get(key) {
+                log::debug!("Using cached response for {} request to {}", method, url);
+                return Ok(result);
+            }
+        }
+
+        if let Some(ref rate_limit) = self.rate_limit {
+            rate_limit.acquire().await;
+        }
+
+        self.check_deadline(method, &url)?;
+
+        log::debug!("Sending {} request to {}", method, url);
+
+        let start = Instant::now();
+        let sent = match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), backend.send(request))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(Error {
+                        kind: ErrorKind::DeadlineExceeded,
+                        message: format!(
+                            "Time budget spent waiting for {} request to {}",
+                            method, url
+                        ),
+                        source: None,
+                    })
+                }),
+            None => backend.send(request).await,
+        };
+        let response = sent.and_then(|response| {
+            if response.status >= 400 {
+                Err(Error::from_status(response.status, &url, &response.body))
+            } else {
+                Ok(response)
+            }
+        });
+        record_request(
+            method,
+            start.elapsed(),
+            response.as_ref().err().map(|e| e.kind),
+        );
+
+        let response = response.map_err(|e| {
+            log::warn!(
+                "Received API error for {} request to {} after {:?}: {}",
+                method,
+                url,
+                start.elapsed(),
+                e
+            );
+            e
+        })?;
+        log::debug!(
+            "Received response for {} request to {} after {:?}",
+            method,
+            url,
+            start.elapsed()
+        );
+
+        let result = response.body;
+        log::trace!("Received: {}", result);
+
+        if let (Some(cache), Some(key)) = (&self.cache, cache_key) {
+            cache.insert(key, result.clone());
+        }
+
+        Ok(result)
+    }
+
+    async fn api_call<B, T>(&self, backend: &B, request: HttpRequest) -> Result<T, Error>
+    where
+        B: HttpBackend,
+        T: DeserializeOwned,
+    {
+        let url = request.url.clone();
+        let body = self.api_call_raw(backend, request).await?;
+        parse_body(&body, &url)
+    }
+
+    async fn fetch_token<B: HttpBackend>(
+        &self,
+        backend: &B,
+        credentials: &Credentials,
+    ) -> Result<api::ValidAuthResponse, Error> {
+        let mut request = HttpRequest::new(HttpMethod::Post, self.url("auth"));
+        request.body = Some(serde_json::to_string(&api::AuthRequest {
+            username: credentials.username.clone(),
+            password: credentials.password.clone(),
+        })?);
+
+        let response = self
+            .api_call::<B, api::AuthResponse>(backend, request)
+            .await?
+            .validate()?;
+
+        log::debug!(
+            "Authenticated with API until {}",
+            response.expiry.api_time()
+        );
+
+        Ok(response)
+    }
+
+    /// Checks whether a username and password are accepted by this endpoint.
+    ///
+    /// Returns `Ok(false)` when the API rejects the credentials and an error
+    /// for any other failure, such as the API being unreachable. The generated
+    /// token is discarded.
+    pub async fn check_credentials(&self, username: &str, password: &str) -> Result<bool, Error> {
+        let credentials = Credentials {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        };
+
+        match self
+            .fetch_token(&ReqwestBackend::new(self), &credentials)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) if error.kind == ErrorKind::NotAuthenticated => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// The username and password used to generate a token.
+#[derive(Clone)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+struct ApiRequest<'a, B: HttpBackend> {
+    api: &'a GlowmarktApi<B>,
+    request: HttpRequest,
+}
+
+impl<B: HttpBackend> ApiRequest<'_, B> {
+    async fn request<T: DeserializeOwned>(self) -> Result<T, Error> {
+        let url = self.request.url.clone();
+        let body = self.send().await?;
+        parse_body(&body, &url)
+    }
+
+    /// Sends the request and returns the body of the response, whatever it
+    /// contains.
+    async fn send(self) -> Result<String, Error> {
+        let api = self.api;
+
+        // Only worth keeping a copy of the request if we can generate a new
+        // token should the current one have expired.
+        let retry = api
+            .credentials
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|_| self.request.clone());
+
+        let token = api.token();
+        let result = api
+            .endpoint
+            .api_call_raw(&api.backend, self.request.header("token", token.clone()))
+            .await;
+
+        // The retry happens immediately, there is no backoff to wait out.
+        match (result, retry) {
+            (Err(error), Some(request)) if error.kind == ErrorKind::NotAuthenticated => {
+                log::debug!("Token was rejected, re-authenticating and retrying once");
+                api.reauthenticate(&token).await?;
+
+                api.endpoint
+                    .api_call_raw(&api.backend, request.header("token", api.token()))
+                    .await
+            }
+            (result, _) => result,
+        }
+    }
+}
+
+type Cache<T> = Arc<Mutex<Option<T>>>;
+
+#[derive(Debug, Clone)]
+/// Access to the Glowmarkt API.
+///
+/// This is cheap to clone and can be shared across threads and tasks. Clones
+/// share the same connection pool as well as any mutable state such as the
+/// current token and caches so a token updated through one clone is seen by
+/// all of them.
+pub struct GlowmarktApi<B: HttpBackend = ReqwestBackend> {
+    token: Arc<RwLock<String>>,
+    credentials: Arc<RwLock<Option<Credentials>>>,
+    reauthenticating: Arc<tokio::sync::Mutex<()>>,
+    account_id: Option<String>,
+    expiry: Arc<Mutex<Option<OffsetDateTime>>>,
+    resource_type_cache: Option<Cache<HashMap<String, api::ResourceType>>>,
+    sampling_check: SamplingCheck,
+    extra_query: Vec<(String, String)>,
+    chunk_concurrency: usize,
+    endpoint: GlowmarktEndpoint,
+    backend: B,
+}
+
+impl GlowmarktApi {
+    /// Create with a provided JWT token.
+    pub fn new(token: &str) -> Self {
+        Self::with_endpoint(Default::default(), token)
+    }
+
+    /// Create with a provided JWT token for a specific endpoint.
+    pub fn with_endpoint(endpoint: GlowmarktEndpoint, token: &str) -> Self {
+        let backend = ReqwestBackend::new(&endpoint);
+        Self::with_backend(endpoint, backend, token)
+    }
+
+    /// Authenticates with the default Glowmarkt API endpoint.
+    ///
+    /// Generates a valid JWT token if successful.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn authenticate(username: &str, password: &str) -> Result<GlowmarktApi, Error> {
+        Self::auth(Default::default(), username, password).await
+    }
+
+    /// Authenticate against a specific endpoint.
+    ///
+    /// The credentials are kept so that a new token can be generated should
+    /// the current one expire during a request.
+    pub async fn auth(
+        endpoint: GlowmarktEndpoint,
+        username: &str,
+        password: &str,
+    ) -> Result<GlowmarktApi, Error> {
+        let backend = ReqwestBackend::new(&endpoint);
+        Self::auth_with_backend(endpoint, backend, username, password).await
+    }
+}
+
+impl<B: HttpBackend> GlowmarktApi<B> {
+    /// Create with a provided JWT token for a specific endpoint, sending
+    /// requests through a custom HTTP backend.
+    pub fn with_backend(endpoint: GlowmarktEndpoint, backend: B, token: &str) -> Self {
+        Self {
+            token: Arc::new(RwLock::new(token.to_owned())),
+            credentials: Default::default(),
+            reauthenticating: Default::default(),
+            account_id: None,
+            expiry: Default::default(),
+            resource_type_cache: None,
+            sampling_check: SamplingCheck::Off,
+            extra_query: Vec::new(),
+            chunk_concurrency: DEFAULT_CHUNK_CONCURRENCY,
+            endpoint,
+            backend,
+        }
+    }
+
+    /// The ID of the authenticated account.
+    ///
+    /// Only known when the API was created by authenticating with a username
+    /// and password.
+    pub fn account_id(&self) -> Option<&str> {
+        self.account_id.as_deref()
+    }
+
+    /// The current JWT token.
+    ///
+    /// This replaces the public `token` field of earlier versions. The token
+    /// is shared with clones and may be replaced when it expires, so a copy is
+    /// returned.
+    pub fn token(&self) -> String {
+        self.token.read().unwrap().clone()
+    }
+
+    /// When the current token expires.
+    ///
+    /// Unknown for a provided token until it has been validated.
+    pub fn expiry(&self) -> Option<OffsetDateTime> {
+        *self.expiry.lock().unwrap()
+    }
+
+    /// Reads the expiry from the `exp` claim of the current token.
+    ///
+    /// The token's signature is not verified so this is only useful as a
+    /// cheap check for a token that has obviously expired. Returns `None` if
+    /// the token is not a JWT or has no expiry.
+    pub fn expiry_from_token(&self) -> Option<OffsetDateTime> {
+        #[derive(Deserialize)]
+        struct Claims {
+            exp: i64,
+        }
+
+        let token = self.token();
+        let payload = token.split('.').nth(1)?;
+        let decoded = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+        let claims: Claims = serde_json::from_slice(&decoded).ok()?;
+
+        api::lenient_timestamp::from_timestamp(claims.exp).ok()
+    }
+
+    /// Enables caching of the known resource types.
+    ///
+    /// Once retrieved the resource types are kept for the lifetime of this
+    /// API and any clones of it.
+    pub fn with_resource_type_cache(mut self) -> Self {
+        self.resource_type_cache = Some(Default::default());
+        self
+    }
+
+    /// Checks the period of requested readings against the sampling of the
+    /// resource's storage.
+    ///
+    /// Requesting readings finer than a resource stores returns padded or
+    /// interpolated values. Checking requires fetching the resource and
+    /// resource types so consider also enabling
+    /// [`Self::with_resource_type_cache`].
+    pub fn with_sampling_check(mut self, check: SamplingCheck) -> Self {
+        self.sampling_check = check;
+        self
+    }
+
+    /// Adds query parameters to every `GET` request.
+    ///
+    /// An escape hatch for parameters the API accepts but this crate does not
+    /// support yet. Apply it to a clone to only affect some requests, the
+    /// clone still shares the token and caches. Parameters already set by a
+    /// method are sent as well, not replaced.
+    pub fn with_extra_query<N, V, I>(mut self, params: I) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+        I: IntoIterator<Item = (N, V)>,
+    {
+        self.extra_query.extend(
+            params
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into())),
+        );
+        self
+    }
+
+    /// Sets how many chunks of a long range of readings are requested at once,
+    /// [`DEFAULT_CHUNK_CONCURRENCY`] by default.
+    ///
+    /// Requests still pass through the endpoint's rate limit. Panics if
+    /// `concurrency` is zero.
+    pub fn with_chunk_concurrency(mut self, concurrency: usize) -> Self {
+        assert!(concurrency > 0, "The concurrency must be positive");
+        self.chunk_concurrency = concurrency;
+        self
+    }
+
+    fn get_request<S>(&self, path: S) -> ApiRequest<'_, B>
+    where
+        S: Display,
+    {
+        let mut request = HttpRequest::new(HttpMethod::Get, self.endpoint.url(path));
+        request.query = self.extra_query.clone();
+
+        ApiRequest { api: self, request }
+    }
+
+    fn query_request<S>(&self, path: S, query: &[(&str, String)]) -> ApiRequest<'_, B>
+    where
+        S: Display,
+    {
+        let mut request = HttpRequest::new(HttpMethod::Get, self.endpoint.url(path));
+        request.query = query
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .chain(self.extra_query.iter().cloned())
+            .collect();
+
+        ApiRequest { api: self, request }
+    }
+
+    fn post_request<S, T>(&self, path: S, data: &T) -> Result<ApiRequest<'_, B>, Error>
+    where
+        S: Display,
+        T: Serialize,
+    {
+        let mut request = HttpRequest::new(HttpMethod::Post, self.endpoint.url(path));
+        request.body = Some(serde_json::to_string(data)?);
+
+        Ok(ApiRequest { api: self, request })
+    }
+}
+
+/// [User System](https://api.glowmarkt.com/api-docs/v0-1/usersys/usertypes/)
+impl<B: HttpBackend> GlowmarktApi<B> {
+    /// Authenticate against a specific endpoint, sending requests through a
+    /// custom HTTP backend.
+    ///
+    /// The credentials are kept so that a new token can be generated should
+    /// the current one expire during a request.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(base_url = %endpoint.base_url))
+    )]
+    pub async fn auth_with_backend(
+        endpoint: GlowmarktEndpoint,
+        backend: B,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, Error> {
+        let credentials = Credentials {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        };
+
+        let response = endpoint.fetch_token(&backend, &credentials).await?;
+
+        Ok(Self {
+            token: Arc::new(RwLock::new(response.token)),
+            credentials: Arc::new(RwLock::new(Some(credentials))),
+            reauthenticating: Default::default(),
+            account_id: response.account_id,
+            expiry: Arc::new(Mutex::new(Some(response.expiry))),
+            resource_type_cache: None,
+            sampling_check: SamplingCheck::Off,
+            extra_query: Vec::new(),
+            chunk_concurrency: DEFAULT_CHUNK_CONCURRENCY,
+            endpoint,
+            backend,
+        })
+    }
+
+    /// Generates a new token from the stored credentials to replace the
+    /// rejected `token`.
+    ///
+    /// Only one new token is generated at a time. Requests that were rejected
+    /// while another one was generating a token use that token instead.
+    async fn reauthenticate(&self, token: &str) -> Result<(), Error> {
+        let _guard = self.reauthenticating.lock().await;
+        if self.token() != token {
+            log::debug!("Token was already replaced, not re-authenticating");
+            return Ok(());
+        }
+
+        let credentials = self.credentials.read().unwrap().clone();
+        let credentials = credentials.ok_or_else(|| Error {
+            kind: ErrorKind::NotAuthenticated,
+            message: "No credentials available to generate a new token".to_string(),
+            source: None,
+        })?;
+
+        let response = self
+            .endpoint
+            .fetch_token(&self.backend, &credentials)
+            .await?;
+
+        *self.token.write().unwrap() = response.token;
+        *self.expiry.lock().unwrap() = Some(response.expiry);
+
+        Ok(())
+    }
+
+    /// Checks that the API is reachable and the current token is valid.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn ping(&self) -> Result<(), Error> {
+        self.validate().await.map(|_| ())
+    }
+
+    /// Validates the current token.
+    ///
+    /// Returns the token's expiry, which is also stored for [`Self::expiry`].
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn validate(&self) -> Result<OffsetDateTime, Error> {
+        let response = self
+            .get_request("auth")
+            .request::<api::ValidateResponse>()
+            .await
+            .and_then(|r| r.validate())?;
+
+        log::debug!(
+            "Authenticated with API until {}",
+            response.expiry.api_time()
+        );
+        *self.expiry.lock().unwrap() = Some(response.expiry);
+
+        Ok(response.expiry)
+    }
+
+    /// Retrieves the profile of the authenticated account.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn account(&self) -> Result<api::Account, Error> {
+        self.get_request("user/me").request().await
+    }
+
+    /// Discards the current token and any stored credentials, consuming the
+    /// client so it cannot be used afterwards.
+    ///
+    /// The API has no way to revoke a token so this only clears local state.
+    /// Clones of this API share the token and credentials so they will no
+    /// longer be authenticated either, nor generate a new token.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub fn logout(self) -> Result<(), Error> {
+        self.token.write().unwrap().clear();
+        *self.credentials.write().unwrap() = None;
+        *self.expiry.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// [Device Management System](https://api.glowmarkt.com/api-docs/v0-1/dmssys/#/)
+impl<B: HttpBackend> GlowmarktApi<B> {
+    /// Retrieves all of the known device types.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn device_types(&self) -> Result<HashMap<String, api::DeviceType>, Error> {
+        self.get_request("devicetype")
+            .request()
+            .await
+            .map(build_map)
+    }
+
+    /// Retrieves a single device type, such as the one a device's
+    /// `device_type_id` refers to.
+    ///
+    /// Fails with [`ErrorKind::NotFound`] if there is no such device type.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn device_type(&self, id: &str) -> Result<api::DeviceType, Error> {
+        self.get_request(format!("devicetype/{}", id))
+            .request()
+            .await
+    }
+
+    /// Retrieves all of the devices registered for an account.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn devices(&self) -> Result<HashMap<String, api::Device>, Error> {
+        self.get_request("device").request().await.map(build_map)
+    }
+
+    /// Retrieves the devices registered for an account that are still active.
+    pub async fn active_devices(&self) -> Result<HashMap<String, api::Device>, Error> {
+        let mut devices = self.devices().await?;
+        devices.retain(|_, device| device.active);
+        Ok(devices)
+    }
+
+    /// Retrieves a single device.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn device(&self, id: &str) -> Result<Option<api::Device>, Error> {
+        match self.get_request(format!("device/{}", id)).request().await {
+            Ok(device) => Ok(Some(device)),
+            Err(error) => {
+                if error.kind == ErrorKind::NotFound {
+                    Ok(None)
+                } else {
+                    Err(error)
+                }
+            }
+        }
+    }
+
+    /// Finds a device from a hardware identifier such as a meter's MPAN.
+    ///
+    /// A device whose primary `hardware_id` matches is preferred over one that
+    /// only lists the identifier in its `hardware_ids`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn device_by_hardware_id(
+        &self,
+        hardware_id: &str,
+    ) -> Result<Option<api::Device>, Error> {
+        let devices = self.devices().await?;
+
+        let (primary, other): (Vec<api::Device>, Vec<api::Device>) = devices
+            .into_values()
+            .filter(|device| {
+                device.hardware_id == hardware_id
+                    || device.hardware_ids.values().any(|id| id == hardware_id)
+            })
+            .partition(|device| device.hardware_id == hardware_id);
+
+        Ok(primary.into_iter().chain(other).next())
+    }
+}
+
+/// [Virtual Entity System](https://api.glowmarkt.com/api-docs/v0-1/vesys/#/)
+impl<B: HttpBackend> GlowmarktApi<B> {
+    /// Retrieves all of the virtual entities registered for an account.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn virtual_entities(&self) -> Result<HashMap<String, api::VirtualEntity>, Error> {
+        self.get_request("virtualentity")
+            .request()
+            .await
+            .map(build_map)
+    }
+
+    /// Creates a new virtual entity.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn create_virtual_entity(
+        &self,
+        request: CreateVirtualEntity,
+    ) -> Result<api::VirtualEntity, Error> {
+        self.post_request("virtualentity", &request)?
+            .request()
+            .await
+    }
+
+    /// Retrieves a single virtual entity by ID.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn virtual_entity(
+        &self,
+        entity_id: &str,
+    ) -> Result<Option<api::VirtualEntity>, Error> {
+        maybe(
+            self.get_request(format!("virtualentity/{}", entity_id))
+                .request()
+                .await,
+        )
+    }
+}
+
+/// [Resource System](https://api.glowmarkt.com/api-docs/v0-1/resourcesys/#/)
+impl<B: HttpBackend> GlowmarktApi<B> {
+    /// Retrieves all of the known resource types.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn resource_types(&self) -> Result<HashMap<String, api::ResourceType>, Error> {
+        if let Some(ref cache) = self.resource_type_cache {
+            if let Some(ref types) = *cache.lock().unwrap() {
+                log::trace!("Using cached resource types");
+                return Ok(types.clone());
+            }
+        }
+
+        let types = self
+            .get_request("resourcetype")
+            .request()
+            .await
+            .map(build_map)?;
+
+        if let Some(ref cache) = self.resource_type_cache {
+            *cache.lock().unwrap() = Some(types.clone());
+        }
+
+        Ok(types)
+    }
+
+    /// Retrieves a single resource type, such as the one a resource's
+    /// `type_id` refers to.
+    ///
+    /// Uses the resource type cache if it has been filled. Fails with
+    /// [`ErrorKind::NotFound`] if there is no such resource type.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn resource_type(&self, id: &str) -> Result<api::ResourceType, Error> {
+        if let Some(ref cache) = self.resource_type_cache {
+            if let Some(ref types) = *cache.lock().unwrap() {
+                log::trace!("Using cached resource types");
+                return types.get(id).cloned().ok_or_else(|| Error {
+                    kind: ErrorKind::NotFound,
+                    message: format!("Unknown resource type {}", id),
+                    source: None,
+                });
+            }
+        }
+
+        self.get_request(format!("resourcetype/{}", id))
+            .request()
+            .await
+    }
+
+    /// Retrieves all resources.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn resources(&self) -> Result<HashMap<String, api::Resource>, Error> {
+        self.get_request("resource").request().await.map(build_map)
+    }
+
+    /// Retrieves all resources that are still active.
+    pub async fn active_resources(&self) -> Result<HashMap<String, api::Resource>, Error> {
+        let mut resources = self.resources().await?;
+        resources.retain(|_, resource| resource.active);
+        Ok(resources)
+    }
+
+    /// Lists the distinct classifiers of the account's resources, sorted.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn known_classifiers(&self) -> Result<Vec<String>, Error> {
+        let classifiers: BTreeSet<String> = self
+            .resources()
+            .await?
+            .into_values()
+            .filter_map(|resource| resource.classifier)
+            .collect();
+
+        record_span("count", classifiers.len());
+        Ok(classifiers.into_iter().collect())
+    }
+
+    /// Retrieves the resources with a matching classifier.
+    ///
+    /// The classifier must match exactly unless it ends with `.*` in which
+    /// case any classifier starting with the rest of it matches, so
+    /// `electricity.*` matches both `electricity.consumption` and
+    /// `electricity.consumption.cost`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn resources_by_classifier(
+        &self,
+        classifier: &str,
+    ) -> Result<Vec<api::Resource>, Error> {
+        let matches = |candidate: &str| match classifier.strip_suffix('*') {
+            Some(prefix) => candidate.starts_with(prefix),
+            None => candidate == classifier,
+        };
+
+        Ok(self
+            .resources()
+            .await?
+            .into_values()
+            .filter(|resource| resource.classifier.as_deref().is_some_and(matches))
+            .collect())
+    }
+
+    /// Retrieves every resource referenced by the account's virtual entities.
+    ///
+    /// Resources referenced by more than one virtual entity are only returned
+    /// once.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn all_resources(&self) -> Result<Vec<api::Resource>, Error> {
+        let entities = self.virtual_entities().await?;
+
+        let resource_ids: HashSet<&str> = entities
+            .values()
+            .flat_map(|entity| entity.resources.iter())
+            .map(|info| info.resource_id.as_str())
+            .collect();
+
+        let resources = join_all(resource_ids.into_iter().map(|id| self.resource(id)))
+            .await
+            .into_iter()
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<api::Resource>, Error>>()?;
+
+        record_span("count", resources.len());
+        Ok(resources)
+    }
+
+    /// Retrieves a set of resources by ID, along with the IDs of any that do
+    /// not exist.
+    ///
+    /// The resources are requested concurrently. Resources that do not exist
+    /// are left out of the map rather than failing the request, their IDs are
+    /// returned in the order they were given.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn resources_by_ids(
+        &self,
+        ids: &[&str],
+    ) -> Result<(HashMap<String, api::Resource>, Vec<String>), Error> {
+        let results = join_all(ids.iter().map(|id| self.resource(id))).await;
+
+        let mut resources = HashMap::new();
+        let mut missing = Vec::new();
+        for (id, result) in ids.iter().zip(results) {
+            match result? {
+                Some(resource) => {
+                    resources.insert(resource.id.clone(), resource);
+                }
+                None => missing.push(id.to_string()),
+            }
+        }
+
+        record_span("count", resources.len());
+        Ok((resources, missing))
+    }
+
+    /// Retrieves the resources of a device's sensors, in the order of the
+    /// sensors.
+    ///
+    /// The resources are requested concurrently. Sensors whose resource does
+    /// not exist are logged and skipped.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, device),
+            fields(device_id = %device.id, count = tracing::field::Empty)
+        )
+    )]
+    pub async fn device_resources(
+        &self,
+        device: &api::Device,
+    ) -> Result<Vec<api::Resource>, Error> {
+        let ids: Vec<&str> = device
+            .protocol
+            .sensors
+            .iter()
+            .map(|sensor| sensor.resource_id.as_str())
+            .collect();
+        let (mut resources, missing) = self.resources_by_ids(&ids).await?;
+        if !missing.is_empty() {
+            log::warn!(
+                "Resources of device {} not found: {}",
+                device.id,
+                missing.join(", ")
+            );
+        }
+
+        let resources: Vec<api::Resource> =
+            ids.iter().filter_map(|id| resources.remove(*id)).collect();
+
+        record_span("count", resources.len());
+        Ok(resources)
+    }
+
+    /// Retrieves the virtual entities, devices, resources and resource types
+    /// of the account and shows how they relate to each other.
+    ///
+    /// See [`build_account_tree`].
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn account_tree(&self) -> Result<AccountTree, Error> {
+        let (entities, devices, resources, resource_types) = futures::try_join!(
+            self.virtual_entities(),
+            self.devices(),
+            self.resources(),
+            self.resource_types(),
+        )?;
+
+        Ok(build_account_tree(
+            &entities,
+            &devices,
+            &resources,
+            &resource_types,
+        ))
+    }
+
+    /// Retrieves a single resource by ID.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn resource(&self, resource_id: &str) -> Result<Option<api::Resource>, Error> {
+        maybe(
+            self.get_request(format!("resource/{}", resource_id))
+                .request()
+                .await,
+        )
+    }
+
+    /// Finds the cost resource that accompanies a consumption resource.
+    ///
+    /// The cost resource is the resource in the virtual entity whose
+    /// classifier is the consumption resource's classifier followed by
+    /// `.cost`, e.g. `electricity.consumption.cost`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, entity)))]
+    pub async fn cost_resource_id(
+        &self,
+        resource_id: &str,
+        entity: &api::VirtualEntity,
+    ) -> Result<Option<String>, Error> {
+        let classifier = match self.resource(resource_id).await? {
+            Some(api::Resource {
+                classifier: Some(classifier),
+                ..
+            }) => format!("{}.cost", classifier),
+            _ => return Ok(None),
+        };
+
+        let ids: Vec<&str> = entity
+            .resources
+            .iter()
+            .map(|info| info.resource_id.as_str())
+            .filter(|id| *id != resource_id)
+            .collect();
+
+        let (resources, _) = self.resources_by_ids(&ids).await?;
+
+        Ok(resources
+            .into_values()
+            .find(|resource| resource.classifier.as_deref() == Some(classifier.as_str()))
+            .map(|resource| resource.id))
+    }
+
+    /// Retrieves the latest tariff that is being applied to a resource.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn latest_tariff(&self, resource_id: &str) -> Result<Vec<TariffData>, Error> {
+        let response: api::LatestTariffResponse = self
+            .get_request(format!("resource/{}/tariff", resource_id))
+            .request()
+            .await?;
+
+        record_span("count", response.data.len());
+        Ok(response.data)
+    }
+
+    /// Retrieves the latest tariff that is being applied to a resource.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn tariff_list(&self, resource_id: &str) -> Result<Vec<TariffListData>, Error> {
+        let response: api::TariffListResponse = self
+            .get_request(format!("resource/{}/tariff-list", resource_id))
+            .request()
+            .await?;
+
+        record_span("count", response.data.len());
+        Ok(response.data)
+    }
+
+    /// Retrieves the time of the first reading available for a resource.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn first_reading_time(&self, resource_id: &str) -> Result<OffsetDateTime, Error> {
+        let response: api::FirstTimeResponse = self
+            .get_request(format!("resource/{}/first-time", resource_id))
+            .request()
+            .await?;
+
+        Ok(response.data.first_ts)
+    }
+
+    /// Retrieves the time of the last reading available for a resource.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub async fn last_reading_time(&self, resource_id: &str) -> Result<OffsetDateTime, Error> {
+        let response: api::LastTimeResponse = self
+            .get_request(format!("resource/{}/last-time", resource_id))
+            .request()
+            .await?;
+
+        Ok(response.data.last_ts)
+    }
+
+    /// Retrieves the readings for a single resource.
+    ///
+    /// The API docs suggest that the start date should be set to the beginning
+    /// of the week (Monday) when the period is `Week` and the beginning of the
+    /// month when the period is `Month`. It is unclear what role the timezone
+    /// plays in this.
+    ///
+    /// The Glowmarkt API behaves strangely in the presence of non-UTC
+    /// timezones so `start` and `end` will first be converted to UTC and all
+    /// returned readings will be in UTC.
+    ///
+    /// `function` determines how the underlying data is combined into each
+    /// reading, cumulative values like consumption and cost should be summed
+    /// while instantaneous values like temperature should be averaged. When
+    /// `None` no function is sent and the API returns the values as stored,
+    /// which matters most for non-additive resources such as temperature or
+    /// humidity sensors.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(
+                start = %start.api_time(),
+                end = %end.api_time(),
+            )
+        )
+    )]
+    pub async fn readings(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+    ) -> Result<Vec<Reading>, Error> {
+        Ok(self
+            .fetch_readings(resource_id, start, end, period, function, &[])
+            .await?
+            .into_iter()
+            .map(|(start, value)| Reading {
+                start,
+                period,
+                value,
+            })
+            .collect())
+    }
+
+    /// Retrieves the readings for a resource along with its unit and
+    /// classifier.
+    ///
+    /// This behaves as [`Self::readings`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, resource, start, end),
+            fields(
+                resource_id = %resource.id,
+                start = %start.api_time(),
+                end = %end.api_time(),
+            )
+        )
+    )]
+    pub async fn resource_readings(
+        &self,
+        resource: &api::Resource,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+    ) -> Result<ResourceReadings, Error> {
+        let readings = self
+            .readings(&resource.id, start, end, period, function)
+            .await?;
+
+        Ok(ResourceReadings {
+            resource_id: resource.id.clone(),
+            classifier: resource.classifier.clone(),
+            unit: resource.base_unit.clone(),
+            currency: resource.currency(),
+            readings,
+        })
+    }
+
+    /// Retrieves the readings for a single resource as `(start, value)` pairs.
+    ///
+    /// This behaves as [`Self::readings`] but skips building a [`Reading`] for
+    /// each value. Values are widened to `f64` for storing in other systems.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(
+                start = %start.api_time(),
+                end = %end.api_time(),
+                count = tracing::field::Empty
+            )
+        )
+    )]
+    pub async fn readings_raw(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+    ) -> Result<Vec<(OffsetDateTime, f64)>, Error> {
+        Ok(self
+            .fetch_readings(resource_id, start, end, period, function, &[])
+            .await?
+            .into_iter()
+            .map(|(start, value)| (start, f64::from(value)))
+            .collect())
+    }
+
+    /// Retrieves the readings for a single resource, adding extra query
+    /// parameters to the request.
+    ///
+    /// This behaves as [`Self::readings`] but allows passing parameters that
+    /// this crate does not otherwise support, such as those controlling how
+    /// the server handles periods without data. Support for these varies
+    /// between deployments of the API and none have been verified against the
+    /// public endpoint, unrecognised parameters are normally ignored.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(
+                start = %start.api_time(),
+                end = %end.api_time(),
+                count = tracing::field::Empty
+            )
+        )
+    )]
+    pub async fn readings_with_params(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+        extra_params: &[(&str, &str)],
+    ) -> Result<Vec<Reading>, Error> {
+        Ok(self
+            .fetch_readings(resource_id, start, end, period, function, extra_params)
+            .await?
+            .into_iter()
+            .map(|(start, value)| Reading {
+                start,
+                period,
+                value,
+            })
+            .collect())
+    }
+
+    async fn fetch_readings(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+        extra_params: &[(&str, &str)],
+    ) -> Result<Vec<(OffsetDateTime, f32)>, Error> {
+        log::trace!(
+            "Requesting readings for {} in range {} to {}, period {:?}, function {:?}",
+            resource_id,
+            start.format(&Rfc3339).unwrap(),
+            end.format(&Rfc3339).unwrap(),
+            period,
+            function
+        );
+
+        self.check_sampling(resource_id, period).await?;
+
+        let mut query = vec![
+            ("from", start.api_time()),
+            ("to", end.api_time()),
+            ("period", period.as_iso8601().to_string()),
+            ("offset", 0.to_string()),
+        ];
+        if let Some(function) = function {
+            query.push(("function", function.as_str().to_string()));
+        }
+        query.extend(
+            extra_params
+                .iter()
+                .map(|(name, value)| (*name, value.to_string())),
+        );
+
+        let response = self
+            .query_request(format!("resource/{}/readings", resource_id), &query)
+            .request::<api::ReadingsResponse>()
+            .await?;
+
+        if response.data.is_empty() {
+            log::warn!(
+                "No readings returned for {} in range {} to {}",
+                resource_id,
+                start.api_time(),
+                end.api_time()
+            );
+        }
+
+        let readings = dedup_readings(resource_id, response.data);
+
+        record_span("count", readings.len());
+        Ok(readings)
+    }
+
+    /// Requests the readings for each chunk, up to `concurrency` at once,
+    /// yielding them in the order of the chunks.
+    fn chunked_readings<'a>(
+        &'a self,
+        resource_id: &'a str,
+        chunks: Vec<(OffsetDateTime, OffsetDateTime)>,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+        concurrency: usize,
+    ) -> impl Stream<Item = Result<Vec<Reading>, Error>> + 'a {
+        stream::iter(chunks)
+            .map(move |(start, end)| async move {
+                self.readings(resource_id, &start, &end, period, function)
+                    .await
+            })
+            .buffered(concurrency)
+    }
+
+    /// Retrieves half-hourly readings for a single resource grouped by the
+    /// calendar day they start on in the `offset` timezone.
+    ///
+    /// The range is requested in chunks the API will accept, see
+    /// [`Self::with_chunk_concurrency`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(start = %start.api_time(), end = %end.api_time())
+        )
+    )]
+    pub async fn readings_grouped_by_day(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        offset: UtcOffset,
+        function: Option<AggregationFunction>,
+    ) -> Result<BTreeMap<Date, Vec<Reading>>, Error> {
+        let period = ReadingPeriod::HalfHour;
+        let mut days: BTreeMap<Date, Vec<Reading>> = BTreeMap::new();
+
+        let mut chunks = self.chunked_readings(
+            resource_id,
+            split_periods(*start, *end, period),
+            period,
+            function,
+            self.chunk_concurrency,
+        );
+        while let Some(chunk) = chunks.try_next().await? {
+            for reading in chunk {
+                days.entry(reading.start.to_offset(offset).date())
+                    .or_default()
+                    .push(reading);
+            }
+        }
+
+        Ok(days)
+    }
+
+    /// Retrieves the consumption readings for a resource along with their cost
+    /// estimated from the resource's tariff.
+    ///
+    /// Each reading is costed using the tariff in effect when it starts, so
+    /// tariff changes within the range are respected. The cost is the unit
+    /// rate multiplied by the consumption plus the share of the daily standing
+    /// charge that falls in the period, in the tariff's currency units
+    /// (normally pence).
+    ///
+    /// This works for resources that have no cost resource of their own.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(start = %start.api_time(), end = %end.api_time())
+        )
+    )]
+    pub async fn readings_with_cost(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+    ) -> Result<Vec<CostedReading>, Error> {
+        let mut rates: Vec<TariffRate> = self
+            .tariff_list(resource_id)
+            .await?
+            .iter()
+            .filter_map(|tariff| {
+                TariffRate::from_plans(
+                    tariff.effective_from().map(|from| from.assume_utc()),
+                    &tariff.plan,
+                )
+            })
+            .collect();
+
+        if rates.is_empty() {
+            rates = self
+                .latest_tariff(resource_id)
+                .await?
+                .iter()
+                .filter_map(|tariff| {
+                    TariffRate::from_plans(Some(tariff.from.assume_utc()), &tariff.plan)
+                })
+                .collect();
+        }
+
+        rates.sort_by_key(|rate| rate.from);
+
+        if rates.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                message: format!("No tariff with a unit rate found for {}", resource_id),
+                source: None,
+            });
+        }
+
+        let mut readings = Vec::new();
+        let mut chunks = self.chunked_readings(
+            resource_id,
+            split_periods(*start, *end, period),
+            period,
+            Some(AggregationFunction::Sum),
+            self.chunk_concurrency,
+        );
+        while let Some(chunk) = chunks.try_next().await? {
+            for reading in chunk {
+                // Readings from before the first known tariff use the earliest.
+                let rate = rates
+                    .iter()
+                    .rev()
+                    .find(|rate| rate.from.is_none_or(|from| from <= reading.start))
+                    .unwrap_or(&rates[0]);
+
+                readings.push(rate.cost(reading));
+            }
+        }
 
-    /// Retrieves a single resource by ID.
-    pub async fn resource(&self, resource_id: &str) -> Result<Option<api::Resource>, Error> {
-        maybe(
-            self.get_request(format!("resource/{}", resource_id))
-                .request()
-                .await,
-        )
+        Ok(readings)
     }
 
-    /// Retrieves the latest tariff that is being applied to a resource.
-    pub async fn latest_tariff(&self, resource_id: &str) -> Result<Vec<TariffData>, Error> {
-        let response: api::LatestTariffResponse = self
-            .get_request(format!("resource/{}/tariff", resource_id))
-            .request()
-            .await?;
+    /// Retrieves the readings for every resource in a virtual entity, keyed by
+    /// resource id.
+    ///
+    /// The resources are read concurrently, each one summed or averaged as
+    /// suits its classifier (see [`aggregation_for_classifier`]) and
+    /// requested in chunks the API will accept.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(start = %start.api_time(), end = %end.api_time())
+        )
+    )]
+    pub async fn entity_readings(
+        &self,
+        entity_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+    ) -> Result<HashMap<String, Vec<Reading>>, Error> {
+        let entity = self.virtual_entity(entity_id).await?.ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            message: format!("Unknown virtual entity {}", entity_id),
+            source: None,
+        })?;
+
+        let ids: Vec<&str> = entity
+            .resources
+            .iter()
+            .map(|resource| resource.resource_id.as_str())
+            .collect();
+        let (resources, missing) = self.resources_by_ids(&ids).await?;
+        if !missing.is_empty() {
+            log::warn!(
+                "Resources of virtual entity {} not found: {}",
+                entity_id,
+                missing.join(", ")
+            );
+        }
 
-        Ok(response.data)
+        let results = join_all(resources.values().map(|resource| async move {
+            let function = aggregation_for_classifier(&resource.classifier);
+
+            let chunks: Vec<Vec<Reading>> = self
+                .chunked_readings(
+                    &resource.id,
+                    split_periods(*start, *end, period),
+                    period,
+                    Some(function),
+                    self.chunk_concurrency,
+                )
+                .try_collect()
+                .await
+                .map_err(|e| e.with_context(format!("Failed to read resource {}", resource.id)))?;
+
+            Ok::<_, Error>((resource.id.clone(), chunks.into_iter().flatten().collect()))
+        }))
+        .await;
+
+        results.into_iter().collect()
     }
 
-    /// Retrieves the latest tariff that is being applied to a resource.
-    pub async fn tariff_list(&self, resource_id: &str) -> Result<Vec<TariffListData>, Error> {
-        let response: api::TariffListResponse = self
-            .get_request(format!("resource/{}/tariff-list", resource_id))
-            .request()
+    /// Retrieves the readings for a single resource with `None` for every
+    /// period that the API returned no reading for.
+    ///
+    /// Every period starting within the range, in UTC, is included so a
+    /// missing reading can be told apart from a genuine reading of zero.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(start = %start.api_time(), end = %end.api_time())
+        )
+    )]
+    pub async fn readings_with_gaps(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+    ) -> Result<Vec<(OffsetDateTime, Option<f64>)>, Error> {
+        let readings = self
+            .fetch_readings(resource_id, start, end, period, function, &[])
             .await?;
 
-        Ok(response.data)
+        Ok(fill_gaps(&readings, *start, *end, period))
     }
 
-    /// Retrieves the readings for a single resource.
-    ///
-    /// The API docs suggest that the start date should be set to the beginning
-    /// of the week (Monday) when the period is `Week` and the beginning of the
-    /// month when the period is `Month`. It is unclear what role the timezone
-    /// plays in this.
+    /// Retrieves the readings for a single resource, limited to the range that
+    /// the resource has readings for.
     ///
-    /// The Glowmarkt API behaves strangely in the presence of non-UTC
-    /// timezones so `start` and `end` will first be converted to UTC and all
-    /// returned readings will be in UTC.
-    pub async fn readings(
+    /// The range is clamped to [`Self::first_reading_time`] and
+    /// [`Self::last_reading_time`], logging a warning if that changes it. No
+    /// readings are returned if the range doesn't overlap the available data.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(start = %start.api_time(), end = %end.api_time())
+        )
+    )]
+    pub async fn readings_clamped(
         &self,
         resource_id: &str,
         start: &OffsetDateTime,
         end: &OffsetDateTime,
         period: ReadingPeriod,
+        function: Option<AggregationFunction>,
     ) -> Result<Vec<Reading>, Error> {
-        log::trace!(
-            "Requesting readings for {} in range {} to {}, period {:?}",
+        let first = self.first_reading_time(resource_id).await?;
+        let last = self.last_reading_time(resource_id).await?;
+
+        let clamped_start = (*start).max(align_to_period(first, period));
+        let clamped_end = (*end).min(last);
+
+        if clamped_start != *start || clamped_end != *end {
+            log::warn!(
+                "Readings for {} are only available from {} to {}, clamping the requested range",
+                resource_id,
+                first.api_time(),
+                last.api_time()
+            );
+        }
+
+        if clamped_start > clamped_end {
+            return Ok(Vec::new());
+        }
+
+        self.readings(resource_id, &clamped_start, &clamped_end, period, function)
+            .await
+    }
+
+    /// Applies the configured [`SamplingCheck`] to a readings request.
+    async fn check_sampling(&self, resource_id: &str, period: ReadingPeriod) -> Result<(), Error> {
+        if self.sampling_check == SamplingCheck::Off {
+            return Ok(());
+        }
+
+        let resource = match self.resource(resource_id).await? {
+            Some(resource) => resource,
+            None => return Ok(()),
+        };
+
+        let sampling = self
+            .resource_types()
+            .await?
+            .get(&resource.type_id)
+            .and_then(|resource_type| {
+                resource_type
+                    .storage
+                    .iter()
+                    .filter_map(|storage| ReadingPeriod::from_iso8601(&storage.sampling))
+                    .min()
+            });
+
+        match sampling {
+            Some(sampling) if period < sampling => {
+                let message = format!(
+                    "Requested {:?} readings for {} but it is only stored every {:?}",
+                    period, resource_id, sampling
+                );
+
+                if self.sampling_check == SamplingCheck::Error {
+                    Err(Error {
+                        kind: ErrorKind::Client,
+                        message,
+                        source: None,
+                    })
+                } else {
+                    log::warn!("{}", message);
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Retrieves the readings for a single resource over a range of any
+    /// length, reporting progress as it goes.
+    ///
+    /// The range is requested in chunks the API will accept, see
+    /// [`Self::with_chunk_concurrency`], and `progress` is called with the
+    /// number of chunks fetched so far and the total number of chunks as they
+    /// complete, in order.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end, progress),
+            fields(
+                start = %start.api_time(),
+                end = %end.api_time(),
+                count = tracing::field::Empty
+            )
+        )
+    )]
+    pub async fn readings_with_progress<F>(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+        mut progress: F,
+    ) -> Result<Vec<Reading>, Error>
+    where
+        F: FnMut(usize, usize),
+    {
+        let chunks = split_periods(*start, *end, period);
+        let total = chunks.len();
+
+        let mut readings = Vec::new();
+        let mut done = 0;
+        let mut chunks = self.chunked_readings(
             resource_id,
-            start.format(&Rfc3339).unwrap(),
-            end.format(&Rfc3339).unwrap(),
-            period
+            chunks,
+            period,
+            function,
+            self.chunk_concurrency,
         );
+        while let Some(chunk) = chunks.try_next().await? {
+            readings.extend(chunk);
+            done += 1;
+            progress(done, total);
+        }
 
-        let period_arg = match period {
-            ReadingPeriod::HalfHour => "PT30M".to_string(),
-            ReadingPeriod::Hour => "PT1H".to_string(),
-            ReadingPeriod::Day => "P1D".to_string(),
-            ReadingPeriod::Week => "P1W".to_string(),
-            ReadingPeriod::Month => "P1M".to_string(),
-            ReadingPeriod::Year => "P1Y".to_string(),
-        };
+        record_span("count", readings.len());
+        Ok(readings)
+    }
 
-        let readings = self
-            .query_request(
-                format!("resource/{}/readings", resource_id),
-                &[
-                    ("from", iso(start.to_offset(UtcOffset::UTC))),
-                    ("to", iso(end.to_offset(UtcOffset::UTC))),
-                    ("period", period_arg),
-                    ("offset", 0.to_string()),
-                    ("function", "sum".to_string()),
-                ],
+    /// Retrieves the readings for a single resource over a range of any
+    /// length, requesting up to `concurrency` chunks at once.
+    ///
+    /// The chunks are those of [`split_periods`] and the readings are returned
+    /// in order. Requests still pass through the endpoint's rate limit.
+    ///
+    /// Panics if `concurrency` is zero.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, start, end),
+            fields(
+                start = %start.api_time(),
+                end = %end.api_time(),
+                count = tracing::field::Empty
             )
-            .request::<api::ReadingsResponse>()
+        )
+    )]
+    pub async fn readings_concurrent(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+        concurrency: usize,
+    ) -> Result<Vec<Reading>, Error> {
+        assert!(concurrency > 0, "The concurrency must be positive");
+
+        let chunks: Vec<Vec<Reading>> = self
+            .chunked_readings(
+                resource_id,
+                split_periods(*start, *end, period),
+                period,
+                function,
+                concurrency,
+            )
+            .try_collect()
             .await?;
 
-        Ok(readings
-            .data
-            .into_iter()
-            .map(|(timestamp, value)| Reading {
-                start: OffsetDateTime::from_unix_timestamp(timestamp).unwrap(),
+        let readings: Vec<Reading> = chunks.into_iter().flatten().collect();
+
+        record_span("count", readings.len());
+        Ok(readings)
+    }
+
+    /// Retrieves every available reading for a single resource.
+    ///
+    /// The available range is found with [`Self::first_reading_time`] and
+    /// [`Self::last_reading_time`] and then requested in chunks the API will
+    /// accept, see [`Self::with_chunk_concurrency`]. Histories longer than
+    /// `max_history` are truncated to the most recent `max_history`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(count = tracing::field::Empty))
+    )]
+    pub async fn readings_all(
+        &self,
+        resource_id: &str,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+        max_history: Duration,
+    ) -> Result<Vec<Reading>, Error> {
+        let mut start = self.first_reading_time(resource_id).await?;
+        let end = self.last_reading_time(resource_id).await?;
+
+        if end - start > max_history {
+            log::warn!(
+                "History for {} starts at {}, only retrieving readings from the last {}",
+                resource_id,
+                start.api_time(),
+                max_history
+            );
+            start = end - max_history;
+        }
+
+        let chunks: Vec<Vec<Reading>> = self
+            .chunked_readings(
+                resource_id,
+                split_periods(
+                    align_to_period(start, period),
+                    align_to_period(end, period),
+                    period,
+                ),
                 period,
-                value,
+                function,
+                self.chunk_concurrency,
+            )
+            .try_collect()
+            .await?;
+        let readings: Vec<Reading> = chunks.into_iter().flatten().collect();
+
+        record_span("count", readings.len());
+        Ok(readings)
+    }
+
+    /// Uploads readings to a resource owned by the account.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self, readings), fields(count = readings.len()))
+    )]
+    pub async fn store_readings(
+        &self,
+        resource_id: &str,
+        readings: &[Reading],
+    ) -> Result<(), Error> {
+        let request = api::StoreReadingsRequest {
+            data: readings
+                .iter()
+                .map(|reading| (reading.start.unix_timestamp(), reading.value))
+                .collect(),
+        };
+
+        // The body of a successful response isn't needed and may be empty.
+        self.post_request(format!("resource/{}/readings", resource_id), &request)?
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Retrieves the readings for a single resource, failing if there are none.
+    ///
+    /// Behaves like [`Self::readings`] except that an empty set of readings
+    /// results in an error of kind [`ErrorKind::NoData`].
+    pub async fn readings_or_err(
+        &self,
+        resource_id: &str,
+        start: &OffsetDateTime,
+        end: &OffsetDateTime,
+        period: ReadingPeriod,
+        function: Option<AggregationFunction>,
+    ) -> Result<Vec<Reading>, Error> {
+        let readings = self
+            .readings(resource_id, start, end, period, function)
+            .await?;
+
+        if readings.is_empty() {
+            Err(Error {
+                kind: ErrorKind::NoData,
+                message: format!(
+                    "No readings for {} in range {} to {}",
+                    resource_id,
+                    start.api_time(),
+                    end.api_time()
+                ),
+                source: None,
             })
-            .collect())
+        } else {
+            Ok(readings)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use backend::HttpResponse;
+
+    use super::*;
+
+    /// Answers logins with a new token each time and rejects every other
+    /// request that doesn't use the latest token. Other `POST` requests get an
+    /// empty response.
+    #[derive(Debug, Clone, Default)]
+    struct StubBackend {
+        state: Arc<Mutex<StubState>>,
+    }
+
+    #[derive(Debug, Default)]
+    struct StubState {
+        logins: usize,
+        valid_token: String,
+    }
+
+    impl StubBackend {
+        fn expire_token(&self) {
+            self.state.lock().unwrap().valid_token.clear();
+        }
+
+        fn logins(&self) -> usize {
+            self.state.lock().unwrap().logins
+        }
+    }
+
+    impl HttpBackend for StubBackend {
+        async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error> {
+            tokio::task::yield_now().await;
+            let mut state = self.state.lock().unwrap();
+
+            if request.method == HttpMethod::Post && request.url.ends_with("/auth") {
+                state.logins += 1;
+                state.valid_token = format!("token-{}", state.logins);
+                return Ok(HttpResponse {
+                    status: 200,
+                    body: format!(
+                        r#"{{"valid": true, "token": "{}", "exp": 1700000000}}"#,
+                        state.valid_token
+                    ),
+                });
+            }
+
+            if request.header_value("token") != Some(state.valid_token.as_str()) {
+                return Ok(HttpResponse {
+                    status: 401,
+                    body: String::new(),
+                });
+            }
+
+            if request.url.ends_with("/missing") {
+                return Ok(HttpResponse {
+                    status: 404,
+                    body: String::new(),
+                });
+            }
+
+            if request.method == HttpMethod::Post {
+                return Ok(HttpResponse {
+                    status: 204,
+                    body: String::new(),
+                });
+            }
+
+            Ok(HttpResponse {
+                status: 200,
+                body: r#"{"valid": true, "exp": 1700000000}"#.to_string(),
+            })
+        }
+    }
+
+    async fn stub_api() -> (GlowmarktApi<StubBackend>, StubBackend) {
+        let backend = StubBackend::default();
+        let api = GlowmarktApi::auth_with_backend(
+            GlowmarktEndpoint::default().without_rate_limit(),
+            backend.clone(),
+            "user",
+            "password",
+        )
+        .await
+        .unwrap();
+
+        (api, backend)
+    }
+
+    fn at(value: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(value, &Rfc3339).unwrap()
+    }
+
+    fn series(start: &str, period: ReadingPeriod, values: &[f32]) -> Vec<Reading> {
+        let mut start = at(start);
+        values
+            .iter()
+            .map(|value| {
+                let reading = Reading::new(start, period, *value);
+                start = reading.end();
+                reading
+            })
+            .collect()
+    }
+
+    #[test]
+    fn downsample_sum_and_average() {
+        let readings = series(
+            "2024-03-01T00:00:00Z",
+            ReadingPeriod::HalfHour,
+            &[1.0, 2.0, 3.0, 6.0],
+        );
+
+        let sums = downsample(&readings, ReadingPeriod::Hour, AggregationFunction::Sum);
+        assert_eq!(
+            sums,
+            series("2024-03-01T00:00:00Z", ReadingPeriod::Hour, &[3.0, 9.0])
+        );
+
+        let averages = downsample(&readings, ReadingPeriod::Hour, AggregationFunction::Average);
+        assert_eq!(
+            averages,
+            series("2024-03-01T00:00:00Z", ReadingPeriod::Hour, &[1.5, 4.5])
+        );
+    }
+
+    #[test]
+    fn downsample_partial_edge_buckets() {
+        let readings = series(
+            "2024-03-01T00:30:00Z",
+            ReadingPeriod::HalfHour,
+            &[4.0, 1.0, 3.0],
+        );
+
+        let sums = downsample(&readings, ReadingPeriod::Hour, AggregationFunction::Sum);
+        assert_eq!(
+            sums,
+            series("2024-03-01T00:00:00Z", ReadingPeriod::Hour, &[4.0, 4.0])
+        );
+
+        let averages = downsample(&readings, ReadingPeriod::Hour, AggregationFunction::Average);
+        assert_eq!(
+            averages,
+            series("2024-03-01T00:00:00Z", ReadingPeriod::Hour, &[4.0, 2.0])
+        );
+    }
+
+    #[test]
+    fn period_starts_half_hours() {
+        assert_eq!(
+            period_starts(
+                at("2024-03-01T00:10:00Z"),
+                at("2024-03-01T01:30:00Z"),
+                ReadingPeriod::HalfHour
+            ),
+            vec![
+                at("2024-03-01T00:30:00Z"),
+                at("2024-03-01T01:00:00Z"),
+                at("2024-03-01T01:30:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn period_starts_week_across_dst() {
+        // The UK clocks went forward on 2024-03-31, a fixed offset ignores it.
+        assert_eq!(
+            period_starts(
+                at("2024-03-25T00:00:00Z"),
+                at("2024-04-08T00:00:00Z"),
+                ReadingPeriod::Week
+            ),
+            vec![
+                at("2024-03-25T00:00:00Z"),
+                at("2024-04-01T00:00:00Z"),
+                at("2024-04-08T00:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn period_starts_months_and_years() {
+        assert_eq!(
+            period_starts(
+                at("2023-11-15T00:00:00+01:00"),
+                at("2024-02-01T00:00:00+01:00"),
+                ReadingPeriod::Month
+            ),
+            vec![
+                at("2023-12-01T00:00:00+01:00"),
+                at("2024-01-01T00:00:00+01:00"),
+                at("2024-02-01T00:00:00+01:00"),
+            ]
+        );
+
+        assert_eq!(
+            period_starts(
+                at("2023-01-01T00:00:00Z"),
+                at("2025-06-01T00:00:00Z"),
+                ReadingPeriod::Year
+            ),
+            vec![
+                at("2023-01-01T00:00:00Z"),
+                at("2024-01-01T00:00:00Z"),
+                at("2025-01-01T00:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn contiguous_series() {
+        for period in [
+            ReadingPeriod::HalfHour,
+            ReadingPeriod::Month,
+            ReadingPeriod::Year,
+        ] {
+            let readings = series("2023-12-31T00:00:00Z", period, &[1.0, 2.0, 3.0, 4.0]);
+            assert!(is_contiguous(&readings), "{:?}", period);
+
+            let gapped = [readings[0].clone(), readings[2].clone()];
+            assert!(!is_contiguous(&gapped), "{:?}", period);
+        }
+
+        assert!(is_contiguous(&[]));
+    }
+
+    #[test]
+    fn duplicate_readings_keep_last() {
+        let readings = dedup_readings(
+            "r1",
+            vec![(1711846800, 1.0), (1711848600, 2.0), (1711846800, 3.0)],
+        );
+
+        assert_eq!(
+            readings,
+            vec![
+                (at("2024-03-31T01:00:00Z"), 3.0),
+                (at("2024-03-31T01:30:00Z"), 2.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn gaps_differ_from_zero_readings() {
+        let readings = vec![
+            (at("2024-03-01T00:00:00Z"), 0.0),
+            (at("2024-03-01T01:00:00Z"), 2.5),
+        ];
+
+        assert_eq!(
+            fill_gaps(
+                &readings,
+                at("2024-03-01T00:00:00Z"),
+                at("2024-03-01T01:30:00Z"),
+                ReadingPeriod::HalfHour
+            ),
+            vec![
+                (at("2024-03-01T00:00:00Z"), Some(0.0)),
+                (at("2024-03-01T00:30:00Z"), None),
+                (at("2024-03-01T01:00:00Z"), Some(2.5)),
+                (at("2024-03-01T01:30:00Z"), None),
+            ]
+        );
+    }
+
+    fn resource_type(units: &[(&str, &str)]) -> api::ResourceType {
+        serde_json::from_value(serde_json::json!({
+            "resourceTypeId": "t1",
+            "name": "electricity consumption",
+            "active": true,
+            "dataSourceType": "DCC",
+            "units": units.iter().cloned().collect::<HashMap<_, _>>(),
+            "storage": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn value_in_converts_from_resource_type_units() {
+        let reading = &series("2024-03-01T00:00:00Z", ReadingPeriod::HalfHour, &[1500.0])[0];
+
+        let energy = resource_type(&[("energy", "Wh")]);
+        assert_eq!(reading.value_in(&energy, "kWh").unwrap(), 1.5);
+
+        let cost = resource_type(&[("currency", "pence")]);
+        assert_eq!(reading.value_in(&cost, "£").unwrap(), 15.0);
+
+        let error = reading.value_in(&energy, "£").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Client);
+        assert!(reading.value_in(&energy, "litres").is_err());
+    }
+
+    #[test]
+    fn api_time_is_utc() {
+        assert_eq!(at("2024-03-01T12:34:56Z").api_time(), "2024-03-01T12:34:56");
+        assert_eq!(
+            at("2024-07-01T01:30:00+01:00").api_time(),
+            "2024-07-01T00:30:00"
+        );
+    }
+
+    #[test]
+    fn api_time_negative_year() {
+        let date = Date::from_calendar_date(-44, Month::March, 15).unwrap();
+        assert_eq!(
+            date.midnight().assume_utc().api_time(),
+            "-0044-03-15T00:00:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn clones_share_refreshed_token() {
+        let (api, backend) = stub_api().await;
+        let clone = api.clone();
+        assert_eq!(clone.token(), "token-1");
+
+        backend.expire_token();
+        clone.ping().await.unwrap();
+
+        assert_eq!(backend.logins(), 2);
+        assert_eq!(api.token(), "token-2");
+
+        api.ping().await.unwrap();
+        assert_eq!(backend.logins(), 2);
+    }
+
+    #[test]
+    fn aggregation_for_classifiers() {
+        let function = |classifier: &str| aggregation_for_classifier(&Some(classifier.to_string()));
+
+        assert_eq!(function("temperature"), AggregationFunction::Average);
+        assert_eq!(function("sensor.humidity"), AggregationFunction::Average);
+        assert_eq!(
+            function("electricity.consumption"),
+            AggregationFunction::Sum
+        );
+        assert_eq!(function("gas.consumption.cost"), AggregationFunction::Sum);
+        assert_eq!(aggregation_for_classifier(&None), AggregationFunction::Sum);
+    }
+
+    #[tokio::test]
+    async fn logout_stops_clones_reauthenticating() {
+        let (api, backend) = stub_api().await;
+        let clone = api.clone();
+
+        api.logout().unwrap();
+        assert_eq!(clone.token(), "");
+
+        let error = clone.ping().await.unwrap_err();
+        assert_eq!(error.kind, ErrorKind::NotAuthenticated);
+        assert_eq!(backend.logins(), 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_device_type_is_not_found() {
+        let (api, _) = stub_api().await;
+
+        let error = api.device_type("missing").await.unwrap_err();
+        assert_eq!(error.kind, ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn unknown_resource_type_is_not_found() {
+        let (api, _) = stub_api().await;
+
+        let error = api.resource_type("missing").await.unwrap_err();
+        assert_eq!(error.kind, ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn missing_resources_are_returned() {
+        let (api, _) = stub_api().await;
+
+        let (resources, missing) = api.resources_by_ids(&["missing"]).await.unwrap();
+        assert!(resources.is_empty());
+        assert_eq!(missing, vec!["missing".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn concurrent_rejections_reauthenticate_once() {
+        let (api, backend) = stub_api().await;
+        backend.expire_token();
+
+        let results = join_all((0..5).map(|_| {
+            let api = api.clone();
+            async move { api.ping().await }
+        }))
+        .await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(backend.logins(), 2);
+    }
+
+    #[tokio::test]
+    async fn store_readings_accepts_empty_response() {
+        let (api, _) = stub_api().await;
+        let readings = series("2024-03-01T00:00:00Z", ReadingPeriod::HalfHour, &[1.0]);
+
+        api.store_readings("r1", &readings).await.unwrap();
+    }
+
+    #[test]
+    fn api_is_send_and_sync() {
+        // Fails to compile if the API can no longer be shared across threads.
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<GlowmarktApi>();
+        assert_send_sync::<Error>();
+    }
+
+    #[test]
+    fn downsample_empty() {
+        assert!(downsample(&[], ReadingPeriod::Day, AggregationFunction::Sum).is_empty());
+        assert!(downsample(&[], ReadingPeriod::Day, AggregationFunction::Average).is_empty());
+    }
+
+    #[test]
+    fn month_end_clamps_to_shorter_month() {
+        let reading = Reading::new(at("2024-01-31T00:00:00Z"), ReadingPeriod::Month, 1.0);
+        assert_eq!(reading.end(), at("2024-02-29T00:00:00Z"));
+
+        let reading = Reading::new(at("2023-12-31T00:00:00Z"), ReadingPeriod::Month, 1.0);
+        assert_eq!(reading.end(), at("2024-01-31T00:00:00Z"));
+        assert_eq!(reading.duration(), Duration::days(31));
+    }
+
+    #[test]
+    fn year_end_from_leap_day() {
+        let reading = Reading::new(at("2024-02-29T12:00:00+01:00"), ReadingPeriod::Year, 1.0);
+        assert_eq!(reading.end(), at("2025-02-28T12:00:00+01:00"));
     }
 }
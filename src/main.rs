@@ -1,21 +1,39 @@
 use std::{
     collections::{BTreeMap, HashMap},
     fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, IsTerminal, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process,
 };
 
 use clap::{Parser, Subcommand};
 use flexi_logger::Logger;
+use futures::{stream, StreamExt};
 use glowmarkt::{
-    align_to_period, split_periods, Device, Error, ErrorKind, GlowmarktApi, ReadingPeriod, Resource,
+    aggregation_for_classifier, align_to_period,
+    influx::{
+        add_tags_for_device, measurements_for_readings, strip_trailing_zero_readings, AnnotatedCsv,
+        Measurement,
+    },
+    split_periods, AggregationFunction, Error, ErrorKind, GlowmarktApi, GlowmarktEndpoint, Reading,
+    ReadingPeriod, ReadingSummary, ResourceNode, APPLICATION_ID, BASE_URL, DEFAULT_RATE_LIMIT,
 };
-use influx::Measurement;
 use serde::Serialize;
-use serde_json::to_string_pretty;
-use time::{format_description::well_known::Iso8601, Duration, OffsetDateTime};
-
-use crate::influx::{add_tags_for_device, add_tags_for_resource, field_for_classifier};
+use serde_json::{to_string, to_string_pretty};
+use time::{
+    format_description::{
+        self,
+        well_known::{Iso8601, Rfc3339},
+    },
+    Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset,
+};
+use tokio::signal;
 
-mod influx;
+mod backfill_state;
+mod influx_writer;
+mod token_cache;
 
 #[derive(Parser)]
 #[clap(author, version)]
@@ -26,7 +44,12 @@ mod influx;
 /// and if not valid a new token will be generated.
 /// Dates can be specified either is ISO-8601 (`2022-08-21T09:00:00Z`) or as a
 /// negative offset from the current time in minutes, so `-1440` would be
-/// interpreted as 24 hours ago.
+/// interpreted as 24 hours ago. Dates without an offset (`2022-08-21T09:00:00`
+/// or `2022-08-21` for midnight) are interpreted in the `--timezone` offset.
+///
+/// Exits with 0 on success, 1 for general failures, 2 for invalid arguments,
+/// 3 if authentication failed, 4 for network errors, 5 when the requested data
+/// does not exist and 6 for errors on the server.
 struct Args {
     #[clap(short, long, env)]
     pub username: Option<String>,
@@ -34,13 +57,61 @@ struct Args {
     pub password: Option<String>,
     #[clap(short, long, env)]
     pub token: Option<String>,
+    /// The URL of the API endpoint.
+    #[clap(long, env, default_value = BASE_URL)]
+    pub base_url: String,
+    /// The application ID to use when communicating with the API.
+    #[clap(long, env, default_value = APPLICATION_ID)]
+    pub app_id: String,
+    /// The maximum number of requests per second to send, 0 for no limit.
+    #[clap(long, env, default_value_t = DEFAULT_RATE_LIMIT)]
+    pub rate_limit: f64,
+    /// Give up on any requests once this many seconds have passed.
+    #[clap(long, env)]
+    pub time_budget: Option<u64>,
+    /// Don't read or store the token in the user's cache directory.
+    #[clap(long)]
+    pub no_cache: bool,
+    #[clap(flatten)]
+    pub options: Options,
 
     #[clap(subcommand)]
     command: Command,
 }
 
+#[derive(clap::Args, Clone, Copy)]
+struct Options {
+    /// The UTC offset for dates given without one, e.g. `+01:00`.
+    #[clap(long, env = "GLOWMARKT_TIMEZONE", default_value = "Z", value_parser = parse_offset)]
+    pub timezone: UtcOffset,
+    /// Print the readings requests that would be made to stderr instead of
+    /// making them.
+    #[clap(long)]
+    pub dry_run: bool,
+    /// Print JSON on a single line instead of pretty-printing it.
+    #[clap(long)]
+    pub compact: bool,
+}
+
+impl Options {
+    fn to_json<T: Serialize + ?Sized>(self, value: &T) -> Result<String, String> {
+        if self.compact {
+            to_string(value).str_err()
+        } else {
+            to_string_pretty(value).str_err()
+        }
+    }
+}
+
 fn parse_tag(val: &str) -> Result<(String, String), String> {
     if let Some(pos) = val.find('=') {
+        if pos == 0 {
+            return Err(format!(
+                "Unable to parse tag '{}', expected 'key=value' but the key is empty.",
+                val
+            ));
+        }
+
         Ok((val[0..pos].to_string(), val[pos + 1..].to_string()))
     } else {
         Err(format!(
@@ -50,10 +121,65 @@ fn parse_tag(val: &str) -> Result<(String, String), String> {
     }
 }
 
+fn parse_offset(val: &str) -> Result<UtcOffset, String> {
+    if val == "Z" || val == "z" {
+        return Ok(UtcOffset::UTC);
+    }
+
+    let format =
+        format_description::parse("[offset_hour sign:mandatory]:[offset_minute]").str_err()?;
+    UtcOffset::parse(val, &format).map_err(|_| {
+        format!(
+            "Unable to parse timezone '{}', expected 'Z' or an offset like '+01:00'.",
+            val
+        )
+    })
+}
+
+#[derive(clap::Args, Clone)]
+struct DateRange {
+    /// Start time of first reading.
+    from: String,
+    /// Start time of last reading (defaults to now).
+    to: Option<String>,
+}
+
+impl DateRange {
+    fn parse(
+        &self,
+        timezone: UtcOffset,
+        period: ReadingPeriod,
+    ) -> Result<(OffsetDateTime, OffsetDateTime), String> {
+        self.parse_at(timezone, period, OffsetDateTime::now_utc())
+    }
+
+    /// Parses the range with relative times and the default end taken from
+    /// `now` rather than the current time.
+    fn parse_at(
+        &self,
+        timezone: UtcOffset,
+        period: ReadingPeriod,
+        now: OffsetDateTime,
+    ) -> Result<(OffsetDateTime, OffsetDateTime), String> {
+        Ok((
+            parse_date(self.from.clone(), timezone, period, now)?,
+            parse_end_date(self.to.clone(), timezone, period, now)?,
+        ))
+    }
+}
+
 #[derive(Subcommand)]
 enum Command {
     /// Generates a valid authentication token.
-    Token,
+    Token {
+        /// Print the token along with its expiry and account as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Checks that the API is reachable and the token is valid.
+    Ping,
+    /// Removes the cached authentication token.
+    Logout,
     /// Lists devices.
     Device {
         /// The specific device to display.
@@ -74,18 +200,51 @@ enum Command {
         /// The specific resource to display.
         id: Option<String>,
     },
+    /// Lists virtual entities.
+    VirtualEntity {
+        /// The specific virtual entity to display.
+        id: Option<String>,
+    },
+    /// Shows how the virtual entities, devices and resources relate to each
+    /// other.
+    Tree {
+        /// Print the tree as JSON.
+        #[clap(long)]
+        json: bool,
+    },
     /// Lists meter readings.
     ///
     /// Times are expressed either in ISO-8601 format (e.g. 2023-11-01T00:00:00Z) or as a
     /// negative offset from the current time in minutes, so `-1440` would be
-    /// interpreted as 24 hours ago.
+    /// interpreted as 24 hours ago. Times without an offset are interpreted in
+    /// the `--timezone` offset.
     Readings {
         /// The resource to read.
         resource_id: String,
-        /// Start time of first reading.
-        from: String,
-        /// Start time of last reading (defaults to now).
-        to: Option<String>,
+        #[clap(flatten)]
+        range: DateRange,
+        /// Print the readings as InfluxDB `line-protocol` or `influx-csv`
+        /// instead of JSON.
+        #[clap(long, value_parser = parse_influx_format)]
+        format: Option<InfluxFormat>,
+    },
+    /// Summarises the meter readings for a resource.
+    ///
+    /// Times are expressed as for the `readings` command.
+    Summary {
+        /// The resource to summarise.
+        resource_id: String,
+        #[clap(flatten)]
+        range: DateRange,
+    },
+    /// Lists meter readings with their cost estimated from the tariff.
+    ///
+    /// Times are expressed as for the `readings` command.
+    Cost {
+        /// The consumption resource to read.
+        resource_id: String,
+        #[clap(flatten)]
+        range: DateRange,
     },
     /// Retrieves the latest tariff that is being applied to a resource.
     Tariff {
@@ -101,73 +260,165 @@ enum Command {
     ///
     /// Times are expressed either in ISO-8601 format (e.g. 2023-11-01T00:00:00Z) or as a
     /// negative offset from the current time in minutes, so `-1440` would be
-    /// interpreted as 24 hours ago.
-    Influx {
-        /// The device to read. If absent all devices are read.
-        #[clap(short, long, env)]
-        device: Option<String>,
-        /// Don't strip trailing zero readings.
-        #[clap(short, long, env)]
-        no_strip: bool,
-        /// Add additional tags to the readings.
-        #[clap(short, long = "tag", value_parser=parse_tag)]
-        tags: Vec<(String, String)>,
-        /// Start time of first reading.
-        from: String,
-        /// Start time of last reading (defaults to now).
-        to: Option<String>,
-    },
+    /// interpreted as 24 hours ago. Times without an offset are interpreted in
+    /// the `--timezone` offset.
+    Influx(InfluxArgs),
+    /// Writes the entire reading history of a resource to a CSV file.
+    ///
+    /// Readings are requested in chunks from the first to the last available
+    /// reading and written as each chunk is received.
+    Backfill(BackfillArgs),
+}
+
+#[derive(clap::Args)]
+struct BackfillArgs {
+    /// The resource to read.
+    resource_id: String,
+    /// The CSV file to write.
+    #[clap(short, long)]
+    output: PathBuf,
+    /// The period of each reading: half-hour, hour, day, week, month or year.
+    #[clap(long, default_value = "half-hour", value_parser = parse_period)]
+    period: ReadingPeriod,
+    /// Append to an existing file, continuing after its last reading.
+    #[clap(long)]
+    resume: bool,
+    /// Record progress in this JSON file and, if it exists, append to the
+    /// output continuing from where the recorded backfill stopped.
+    #[clap(long, conflicts_with = "resume")]
+    state: Option<PathBuf>,
+    /// The maximum number of requests to make to the API at once.
+    #[clap(long, env, default_value = "4")]
+    concurrency: NonZeroUsize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InfluxFormat {
+    LineProtocol,
+    Csv,
+}
+
+fn parse_influx_format(val: &str) -> Result<InfluxFormat, String> {
+    match val {
+        "line-protocol" => Ok(InfluxFormat::LineProtocol),
+        "influx-csv" => Ok(InfluxFormat::Csv),
+        _ => Err(format!("Unknown format '{}'.", val)),
+    }
+}
+
+fn parse_period(val: &str) -> Result<ReadingPeriod, String> {
+    match val {
+        "half-hour" => Ok(ReadingPeriod::HalfHour),
+        "hour" => Ok(ReadingPeriod::Hour),
+        "day" => Ok(ReadingPeriod::Day),
+        "week" => Ok(ReadingPeriod::Week),
+        "month" => Ok(ReadingPeriod::Month),
+        "year" => Ok(ReadingPeriod::Year),
+        _ => Err(format!("Unknown period '{}'.", val)),
+    }
+}
+
+#[derive(clap::Args)]
+struct InfluxArgs {
+    /// The device to read. If absent all devices are read.
+    #[clap(short, long, env)]
+    device: Option<String>,
+    /// Don't strip trailing zero readings.
+    #[clap(short, long, env)]
+    no_strip: bool,
+    /// Also read devices and resources that are no longer active.
+    #[clap(long, env)]
+    include_inactive: bool,
+    /// The measurement name to use.
+    #[clap(short, long, env, default_value = "glowmarkt")]
+    measurement: String,
+    /// The output format, `line-protocol` or `influx-csv` for InfluxDB v2
+    /// annotated CSV.
+    #[clap(long, env, default_value = "line-protocol", value_parser = parse_influx_format)]
+    format: InfluxFormat,
+    /// Write the measurements to the InfluxDB v2 server at this URL instead of
+    /// printing them.
+    #[clap(long, env, requires = "bucket")]
+    influx_url: Option<String>,
+    /// The API token to use with the InfluxDB server.
+    #[clap(long, env)]
+    influx_token: Option<String>,
+    /// The InfluxDB organization that owns the bucket.
+    #[clap(long, env)]
+    influx_org: Option<String>,
+    /// The InfluxDB bucket to write to.
+    #[clap(long, env = "INFLUX_BUCKET", requires = "influx-url")]
+    bucket: Option<String>,
+    /// Add additional tags to every reading, as `key=value`. May be repeated.
+    #[clap(short, long = "tag", value_parser=parse_tag)]
+    tags: Vec<(String, String)>,
+    /// Carry on with the remaining resources if reading one fails.
+    ///
+    /// The command still fails once all available readings have been output.
+    #[clap(long = "continue")]
+    r#continue: bool,
+    /// The maximum number of requests to make to the API at once.
+    #[clap(long, env, default_value = "4")]
+    concurrency: NonZeroUsize,
+    /// Output measurements once at least this many are complete instead of
+    /// holding them all until every reading has been fetched.
+    #[clap(long, env)]
+    batch_size: Option<NonZeroUsize>,
+    #[clap(flatten)]
+    range: DateRange,
 }
 
-fn parse_date(date: String, period: ReadingPeriod) -> Result<OffsetDateTime, String> {
+fn parse_datetime(date: &str, timezone: UtcOffset) -> Result<OffsetDateTime, String> {
+    if let Ok(date) = OffsetDateTime::parse(date, &Iso8601::DEFAULT) {
+        return Ok(date);
+    }
+
+    let datetime_format =
+        format_description::parse("[year]-[month]-[day]T[hour]:[minute]:[second]").str_err()?;
+    if let Ok(date) = PrimitiveDateTime::parse(date, &datetime_format) {
+        return Ok(date.assume_offset(timezone));
+    }
+
+    let date_format = format_description::parse("[year]-[month]-[day]").str_err()?;
+    if let Ok(date) = Date::parse(date, &date_format) {
+        return Ok(date.with_time(Time::MIDNIGHT).assume_offset(timezone));
+    }
+
+    Err(format!(
+        "Couldn't parse the date '{date}', accepted formats are '2023-01-01T00:00:00Z', \
+        '2023-01-01T00:00:00' and '2023-01-01' or a negative number of minutes like '-1440'"
+    ))
+}
+
+fn parse_date(
+    date: String,
+    timezone: UtcOffset,
+    period: ReadingPeriod,
+    now: OffsetDateTime,
+) -> Result<OffsetDateTime, String> {
     if let Some(date) = date.strip_prefix('-') {
         let offset = date.parse::<i64>().str_err()?;
-        Ok(align_to_period(
-            OffsetDateTime::now_utc() - Duration::minutes(offset),
-            period,
-        ))
+        Ok(align_to_period(now - Duration::minutes(offset), period))
     } else {
-        OffsetDateTime::parse(&date, &Iso8601::DEFAULT)
-            .map_err(|_| {
-                format!("Couldn't format the date '{date}' as ISO-8601, try '2023-01-01T00:00:00Z'")
-            })
-            .and_then(|date| {
-                let now = OffsetDateTime::now_utc();
-                if date > now {
-                    Err("Cannot use a date that is in the future.".to_string())
-                } else {
-                    Ok(align_to_period(date, period))
-                }
-            })
+        let date = parse_datetime(&date, timezone)?;
+        if date > now {
+            Err("Cannot use a date that is in the future.".to_string())
+        } else {
+            Ok(align_to_period(date, period))
+        }
     }
 }
 
-fn parse_end_date(date: Option<String>, period: ReadingPeriod) -> Result<OffsetDateTime, String> {
+fn parse_end_date(
+    date: Option<String>,
+    timezone: UtcOffset,
+    period: ReadingPeriod,
+    now: OffsetDateTime,
+) -> Result<OffsetDateTime, String> {
     if let Some(date) = date {
-        if let Some(date) = date.strip_prefix('-') {
-            let offset = date.parse::<i64>().str_err()?;
-            Ok(align_to_period(
-                OffsetDateTime::now_utc() - Duration::minutes(offset),
-                period,
-            ))
-        } else {
-            OffsetDateTime::parse(&date, &Iso8601::DEFAULT)
-                .map_err(|_| {
-                    format!(
-                        "Couldn't format the date '{date}' as ISO-8601, try '2023-01-01T00:00:00Z'"
-                    )
-                })
-                .and_then(|date| {
-                    let now = OffsetDateTime::now_utc();
-                    if date > now {
-                        Err("Cannot use a date that is in the future.".to_string())
-                    } else {
-                        Ok(align_to_period(date, period))
-                    }
-                })
-        }
+        parse_date(date, timezone, period, now)
     } else {
-        Ok(align_to_period(OffsetDateTime::now_utc(), period))
+        Ok(align_to_period(now, period))
     }
 }
 
@@ -181,6 +432,66 @@ impl<V, D: Display> ErrorStr<V> for Result<V, D> {
     }
 }
 
+/// An error that stops the command along with the process exit code to use.
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    const GENERAL: i32 = 1;
+    const USAGE: i32 = 2;
+    const AUTH: i32 = 3;
+    const NETWORK: i32 = 4;
+    const NO_DATA: i32 = 5;
+    const SERVER: i32 = 6;
+
+    fn usage(message: String) -> Self {
+        Self {
+            code: Self::USAGE,
+            message,
+        }
+    }
+
+    fn no_data(message: String) -> Self {
+        Self {
+            code: Self::NO_DATA,
+            message,
+        }
+    }
+
+    fn code_for(kind: ErrorKind) -> i32 {
+        match kind {
+            ErrorKind::NotAuthenticated => Self::AUTH,
+            ErrorKind::Network | ErrorKind::TruncatedResponse => Self::NETWORK,
+            ErrorKind::NotFound | ErrorKind::NoData => Self::NO_DATA,
+            ErrorKind::Server => Self::SERVER,
+            ErrorKind::Client
+            | ErrorKind::Response
+            | ErrorKind::RangeTooLarge
+            | ErrorKind::DeadlineExceeded => Self::GENERAL,
+        }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Self {
+            code: Self::GENERAL,
+            message,
+        }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Self {
+            code: Self::code_for(error.kind),
+            message: error.to_string(),
+        }
+    }
+}
+
 fn values<T>(map: HashMap<String, T>) -> Vec<T> {
     map.into_values().collect()
 }
@@ -188,212 +499,838 @@ fn values<T>(map: HashMap<String, T>) -> Vec<T> {
 fn display_result<T: Serialize>(
     items: Result<HashMap<String, T>, Error>,
     id: Option<String>,
-) -> Result<(), String> {
-    let items = items.str_err()?;
+    what: &str,
+    options: Options,
+) -> Result<(), Failure> {
+    let items = items?;
 
     if let Some(id) = id {
-        println!("{}", to_string_pretty(&items.get(&id)).str_err()?);
+        let item = items
+            .get(&id)
+            .ok_or_else(|| Failure::no_data(format!("Unknown {} {}", what, id)))?;
+        println!("{}", options.to_json(item)?);
     } else {
-        println!("{}", to_string_pretty(&values(items)).str_err()?);
+        println!("{}", options.to_json(&values(items))?);
     }
 
     Ok(())
 }
 
+fn print_readings_request(
+    resource_id: &str,
+    start: &OffsetDateTime,
+    end: &OffsetDateTime,
+    period: ReadingPeriod,
+    function: AggregationFunction,
+) {
+    eprintln!(
+        "GET resource/{}/readings from {} to {}, period {:?}, function {}",
+        resource_id,
+        start.format(&Rfc3339).unwrap(),
+        end.format(&Rfc3339).unwrap(),
+        period,
+        function.as_str()
+    );
+}
+
 async fn readings(
     api: GlowmarktApi,
     resource: String,
-    start: String,
-    end: Option<String>,
-) -> Result<(), String> {
+    range: DateRange,
+    format: Option<InfluxFormat>,
+    options: Options,
+) -> Result<(), Failure> {
     let period = ReadingPeriod::HalfHour;
-    let start = parse_date(start, period)?;
-    let end = parse_end_date(end, period)?;
+    let (start, end) = range
+        .parse(options.timezone, period)
+        .map_err(Failure::usage)?;
     let ranges = split_periods(start, end, period);
 
+    // The resource is only needed for the tags of InfluxDB measurements.
+    let details = match format {
+        Some(_) if !options.dry_run => Some(
+            api.resource(&resource)
+                .await?
+                .ok_or_else(|| Failure::no_data(format!("Unknown resource {}", resource)))?,
+        ),
+        _ => None,
+    };
+    let mut csv = (format == Some(InfluxFormat::Csv)).then(AnnotatedCsv::new);
+
     for (start, end) in ranges {
+        if options.dry_run {
+            print_readings_request(&resource, &start, &end, period, AggregationFunction::Sum);
+            continue;
+        }
+
         let readings = api
-            .readings(&resource, &start, &end, period)
-            .await
-            .str_err()?;
+            .readings(
+                &resource,
+                &start,
+                &end,
+                period,
+                Some(AggregationFunction::Sum),
+            )
+            .await?;
 
-        println!("{}", to_string_pretty(&readings).str_err()?);
+        match details {
+            Some(ref details) => {
+                let measurements =
+                    measurements_for_readings("glowmarkt", details, &BTreeMap::new(), &readings);
+                for line in format_measurements(BTreeMap::from([(0, measurements)]), csv.as_mut()) {
+                    println!("{}", line);
+                }
+            }
+            None => println!("{}", options.to_json(&readings)?),
+        }
     }
 
     Ok(())
 }
 
-async fn latest_tariff(api: GlowmarktApi, resource: String) -> Result<(), String> {
-    let tariff = api.latest_tariff(&resource).await.str_err()?;
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenInfo {
+    token: String,
+    #[serde(with = "time::serde::rfc3339::option")]
+    expiry: Option<OffsetDateTime>,
+    account_id: Option<String>,
+}
 
-    println!("{}", to_string_pretty(&tariff).str_err()?);
+#[derive(Serialize)]
+struct ResourceSummary {
+    resource_id: String,
+    unit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<String>,
+    #[serde(flatten)]
+    summary: ReadingSummary,
+}
+
+fn format_resource_node(node: &ResourceNode) -> String {
+    let mut line = format!(
+        "{} ({})",
+        node.name.as_deref().unwrap_or("<unknown resource>"),
+        node.resource_id
+    );
+
+    let details: Vec<&str> = [
+        node.resource_type.as_deref(),
+        node.classifier.as_deref(),
+        node.unit.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !details.is_empty() {
+        line.push_str(&format!(" [{}]", details.join(", ")));
+    }
+
+    line
+}
+
+async fn tree(api: GlowmarktApi, json: bool, options: Options) -> Result<(), Failure> {
+    let tree = api.account_tree().await?;
+
+    if json {
+        println!("{}", options.to_json(&tree)?);
+        return Ok(());
+    }
+
+    println!("Virtual entities");
+    for entity in &tree.entities {
+        println!("  {} ({})", entity.name, entity.entity_id);
+        for resource in &entity.resources {
+            println!("    {}", format_resource_node(resource));
+        }
+    }
+
+    println!("Devices");
+    for device in &tree.devices {
+        println!(
+            "  {} ({}, hardware {})",
+            device.description.as_deref().unwrap_or("<no description>"),
+            device.device_id,
+            device.hardware_id
+        );
+        for sensor in &device.sensors {
+            println!("    {}", format_resource_node(sensor));
+        }
+    }
+
+    Ok(())
+}
+
+async fn summary(
+    api: GlowmarktApi,
+    resource_id: String,
+    range: DateRange,
+    options: Options,
+) -> Result<(), Failure> {
+    let period = ReadingPeriod::HalfHour;
+    let (start, end) = range
+        .parse(options.timezone, period)
+        .map_err(Failure::usage)?;
+
+    let resource = api
+        .resource(&resource_id)
+        .await?
+        .ok_or_else(|| Failure::no_data(format!("Unknown resource {}", resource_id)))?;
+    let function = aggregation_for_classifier(&resource.classifier);
+
+    let mut readings = Vec::new();
+    for (start, end) in split_periods(start, end, period) {
+        if options.dry_run {
+            print_readings_request(&resource_id, &start, &end, period, function);
+            continue;
+        }
+
+        readings.extend(
+            api.readings(&resource_id, &start, &end, period, Some(function))
+                .await?,
+        );
+    }
+
+    if options.dry_run {
+        return Ok(());
+    }
+
+    let summary = ResourceSummary {
+        resource_id,
+        currency: resource.currency(),
+        unit: resource.base_unit,
+        summary: ReadingSummary::from_readings(&readings),
+    };
+
+    println!("{}", options.to_json(&summary)?);
 
     Ok(())
 }
 
-async fn tariff_list(api: GlowmarktApi, resource: String) -> Result<(), String> {
-    let tariff = api.tariff_list(&resource).await.str_err()?;
+async fn cost(
+    api: GlowmarktApi,
+    resource: String,
+    range: DateRange,
+    options: Options,
+) -> Result<(), Failure> {
+    let period = ReadingPeriod::HalfHour;
+    let (start, end) = range
+        .parse(options.timezone, period)
+        .map_err(Failure::usage)?;
+
+    if options.dry_run {
+        for (start, end) in split_periods(start, end, period) {
+            print_readings_request(&resource, &start, &end, period, AggregationFunction::Sum);
+        }
+        return Ok(());
+    }
 
-    println!("{}", to_string_pretty(&tariff).str_err()?);
+    let readings = api
+        .readings_with_cost(&resource, &start, &end, period)
+        .await?;
+
+    println!("{}", options.to_json(&readings)?);
 
     Ok(())
 }
 
-async fn influx(
+async fn latest_tariff(
     api: GlowmarktApi,
-    device: Option<String>,
-    no_strip: bool,
-    tags: BTreeMap<String, String>,
-    start: String,
-    end: Option<String>,
+    resource: String,
+    options: Options,
+) -> Result<(), Failure> {
+    let tariff = api.latest_tariff(&resource).await?;
+
+    println!("{}", options.to_json(&tariff)?);
+
+    Ok(())
+}
+
+async fn tariff_list(api: GlowmarktApi, resource: String, options: Options) -> Result<(), Failure> {
+    let tariff = api.tariff_list(&resource).await?;
+
+    println!("{}", options.to_json(&tariff)?);
+
+    Ok(())
+}
+
+/// Formats measurements as line protocol or, if a CSV formatter is given, as
+/// annotated CSV.
+fn format_measurements(
+    measurements: BTreeMap<i128, Vec<Measurement>>,
+    mut csv: Option<&mut AnnotatedCsv>,
+) -> Vec<String> {
+    let measurements = measurements.into_values().flatten();
+
+    match csv {
+        Some(ref mut csv) => csv.format_all(measurements),
+        None => measurements
+            .map(|measurement| measurement.to_string())
+            .collect(),
+    }
+}
+
+/// Prints the formatted measurements or writes them to InfluxDB.
+async fn write_measurements(
+    lines: Vec<String>,
+    writer: Option<&influx_writer::InfluxWriter>,
 ) -> Result<(), String> {
+    match writer {
+        Some(writer) => writer.write(&lines).await,
+        None => {
+            for line in lines {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Formats the measurements with timestamps before `before` for output.
+///
+/// When stripping, trailing timestamps that are all zero are kept back until
+/// a later non-zero measurement shows that they are not at the end.
+fn flush_measurements(
+    measurements: &mut BTreeMap<i128, Vec<Measurement>>,
+    before: i128,
+    strip: bool,
+    csv: Option<&mut AnnotatedCsv>,
+) -> Vec<String> {
+    let mut pending = measurements.split_off(&before);
+
+    if strip {
+        pending.append(&mut strip_trailing_zero_readings(measurements));
+    }
+
+    format_measurements(std::mem::replace(measurements, pending), csv)
+}
+
+async fn influx(api: GlowmarktApi, args: InfluxArgs, options: Options) -> Result<(), Failure> {
+    if args.influx_url.is_some() && args.format == InfluxFormat::Csv {
+        return Err(Failure::usage(
+            "InfluxDB only accepts line protocol when writing directly.".to_string(),
+        ));
+    }
+
     let period = ReadingPeriod::HalfHour;
-    let start = parse_date(start, period)?;
-    let end = parse_end_date(end, period)?;
+    let (start, end) = args
+        .range
+        .parse(options.timezone, period)
+        .map_err(Failure::usage)?;
     let ranges = split_periods(start, end, period);
 
-    let mut measurements = BTreeMap::new();
+    let resources = if args.include_inactive {
+        api.resources().await?
+    } else {
+        api.active_resources().await?
+    };
 
-    let resources = api.resources().await?;
+    let devices = if let Some(ref device) = args.device {
+        match api.device(device).await? {
+            Some(device) => vec![device],
+            None => return Err(Failure::no_data(format!("Unknown device {}", device))),
+        }
+    } else if args.include_inactive {
+        api.devices().await?.into_values().collect()
+    } else {
+        api.active_devices().await?.into_values().collect()
+    };
 
-    async fn process_device(
-        api: &GlowmarktApi,
-        tags: &BTreeMap<String, String>,
-        resources: &HashMap<String, Resource>,
-        device: Device,
-        ranges: &Vec<(OffsetDateTime, OffsetDateTime)>,
-        measurements: &mut BTreeMap<OffsetDateTime, Vec<Measurement>>,
-    ) -> Result<(), Error> {
-        let mut tags = tags.clone();
+    let mut sources = Vec::new();
+    for device in devices {
+        let mut tags: BTreeMap<String, String> = args.tags.iter().cloned().collect();
         add_tags_for_device(&mut tags, &device);
 
         for sensor in device.protocol.sensors {
             if let Some(resource) = resources.get(&sensor.resource_id) {
-                let mut tags = tags.clone();
-                add_tags_for_resource(&mut tags, resource);
-
-                for (start, end) in ranges {
-                    let readings = match api
-                        .readings(&resource.id, start, end, ReadingPeriod::HalfHour)
-                        .await
-                    {
-                        Ok(r) => r,
-                        Err(_) => return Ok(()),
-                    };
-
-                    for reading in readings {
-                        let mut measurement =
-                            Measurement::new("glowmarkt", reading.start, tags.clone());
-                        measurement.add_field(
-                            field_for_classifier(&resource.classifier),
-                            reading.value as f64,
-                        );
-
-                        measurements
-                            .entry(reading.start)
-                            .or_default()
-                            .push(measurement);
-                    }
-                }
+                sources.push((tags.clone(), resource));
             }
         }
+    }
+
+    // Every resource is requested for a range before moving on to the next so
+    // that earlier timestamps are complete and can be output in batches.
+    let requests = ranges.iter().flat_map(|(start, end)| {
+        sources
+            .iter()
+            .map(move |(tags, resource)| (tags, *resource, start, end))
+    });
+
+    if options.dry_run {
+        for (_, resource, start, end) in requests {
+            let function = aggregation_for_classifier(&resource.classifier);
+            print_readings_request(&resource.id, start, end, period, function);
+        }
 
-        Ok(())
+        return Ok(());
     }
 
-    if let Some(device) = device {
-        if let Some(device) = api.device(&device).await? {
-            process_device(&api, &tags, &resources, device, &ranges, &mut measurements).await?;
-        } else {
-            eprintln!("Error: Unknown device {}", device);
+    // Requests run concurrently but results are returned in order so the
+    // measurements are merged here on a single task.
+    let mut results = stream::iter(requests)
+        .map(|(tags, resource, start, end)| {
+            let api = &api;
+            async move {
+                let function = aggregation_for_classifier(&resource.classifier);
+                let readings = api
+                    .readings(&resource.id, start, end, period, Some(function))
+                    .await;
+                (tags, resource, end, readings)
+            }
+        })
+        .buffered(args.concurrency.get());
+
+    let mut measurements: BTreeMap<i128, Vec<Measurement>> = BTreeMap::new();
+    let mut csv = (args.format == InfluxFormat::Csv).then(AnnotatedCsv::new);
+    let writer = match (&args.influx_url, &args.bucket) {
+        (Some(url), Some(bucket)) => Some(influx_writer::InfluxWriter::new(
+            url,
+            args.influx_token.clone(),
+            args.influx_org.clone(),
+            bucket.clone(),
+        )),
+        _ => None,
+    };
+    let mut failures = 0;
+    let mut received = 0;
+    let mut interrupt = Box::pin(signal::ctrl_c());
+    while let Some((tags, resource, end, readings)) = tokio::select! {
+        next = results.next() => next,
+        _ = &mut interrupt => {
+            eprintln!("Interrupted, writing the measurements received so far.");
+            None
         }
-    } else {
-        let devices = api.devices().await?.into_values();
-        for device in devices {
-            process_device(&api, &tags, &resources, device, &ranges, &mut measurements).await?;
+    } {
+        received += 1;
+
+        let readings = match readings {
+            Ok(r) => r,
+            Err(e) if args.r#continue => {
+                log::error!("Failed to read resource {}: {}", resource.id, e);
+                failures += 1;
+                continue;
+            }
+            Err(e) => {
+                return Err(e
+                    .with_context(format!("Failed to read resource {}", resource.id))
+                    .into())
+            }
+        };
+
+        for measurement in measurements_for_readings(&args.measurement, resource, tags, &readings) {
+            measurements
+                .entry(measurement.timestamp)
+                .or_default()
+                .push(measurement);
         }
-    }
 
-    if !no_strip {
-        let timestamps: Vec<OffsetDateTime> = measurements.keys().rev().cloned().collect();
-        for timestamp in timestamps {
-            if measurements
-                .get(&timestamp)
-                .unwrap()
-                .iter()
-                .all(|m| m.fields.iter().all(|(_, v)| *v == 0.0))
+        if let Some(batch_size) = args.batch_size {
+            let range_complete = received % sources.len() == 0;
+            if range_complete
+                && measurements.values().map(Vec::len).sum::<usize>() >= batch_size.get()
             {
-                measurements.remove(&timestamp);
+                let lines = flush_measurements(
+                    &mut measurements,
+                    end.unix_timestamp_nanos(),
+                    !args.no_strip,
+                    csv.as_mut(),
+                );
+                write_measurements(lines, writer.as_ref()).await?;
             }
         }
     }
 
-    for (_, measurements) in measurements {
-        for measurement in measurements {
-            println!("{}", measurement);
-        }
+    if !args.no_strip {
+        strip_trailing_zero_readings(&mut measurements);
+    }
+
+    write_measurements(
+        format_measurements(measurements, csv.as_mut()),
+        writer.as_ref(),
+    )
+    .await?;
+
+    if failures > 0 {
+        return Err(format!("Failed to read {} of the requested ranges", failures).into());
     }
 
     Ok(())
 }
 
-async fn login(args: &Args) -> Result<GlowmarktApi, String> {
-    if let Some(ref token) = args.token {
-        let api = GlowmarktApi::new(token);
+/// Finds the start of the reading following the last one in an existing CSV
+/// file.
+fn resume_from(path: &Path, period: ReadingPeriod) -> Result<Option<OffsetDateTime>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
 
-        match api.validate().await {
-            Ok(_) => {
-                return Ok(api);
-            }
-            Err(e) => {
-                if e.kind != ErrorKind::NotAuthenticated {
-                    return Err(e.to_string());
-                }
+    let data = fs::read_to_string(path).str_err()?;
+    let last = match data.lines().skip(1).filter(|line| !line.is_empty()).last() {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let timestamp = last.split(',').next().unwrap_or_default();
+    let start = OffsetDateTime::parse(timestamp, &Rfc3339)
+        .map_err(|e| format!("Unable to parse last reading '{}': {}", last, e))?;
+
+    Ok(Some(Reading::new(start, period, 0.0).end()))
+}
+
+async fn backfill(api: GlowmarktApi, args: BackfillArgs, options: Options) -> Result<(), Failure> {
+    let period = args.period;
+
+    let resource = api
+        .resource(&args.resource_id)
+        .await?
+        .ok_or_else(|| Failure::no_data(format!("Unknown resource {}", args.resource_id)))?;
+    let function = aggregation_for_classifier(&resource.classifier);
+
+    let state = match args.state {
+        Some(ref path) => backfill_state::load(path)?,
+        None => None,
+    };
+
+    let resumed = if let Some(state) = state {
+        state
+            .check(&resource.id, period, &args.output)
+            .map_err(Failure::usage)?;
+        Some(state.next)
+    } else if args.resume {
+        resume_from(&args.output, period)?
+    } else {
+        None
+    };
+
+    let first = match resumed {
+        Some(start) => start,
+        None => align_to_period(api.first_reading_time(&resource.id).await?, period),
+    };
+    let last = align_to_period(api.last_reading_time(&resource.id).await?, period);
+
+    if first > last {
+        eprintln!("Already up to date.");
+        return Ok(());
+    }
+
+    let ranges = split_periods(first, last, period);
+
+    if options.dry_run {
+        for (start, end) in ranges {
+            print_readings_request(&resource.id, &start, &end, period, function);
+        }
+
+        return Ok(());
+    }
+
+    let mut file = if resumed.is_some() {
+        BufWriter::new(
+            OpenOptions::new()
+                .append(true)
+                .open(&args.output)
+                .str_err()?,
+        )
+    } else {
+        let mut file = BufWriter::new(File::create(&args.output).str_err()?);
+        writeln!(file, "timestamp,value").str_err()?;
+        file
+    };
+
+    let mut interrupt = Box::pin(signal::ctrl_c());
+
+    // Chunks are requested concurrently but returned in order so they can be
+    // appended to the file as they arrive.
+    let count = ranges.len();
+    let mut chunks = stream::iter(ranges)
+        .map(|(start, end)| {
+            let api = &api;
+            let resource_id = &resource.id;
+            async move {
+                let readings = api
+                    .readings(resource_id, &start, &end, period, Some(function))
+                    .await;
+                (start, end, readings)
             }
+        })
+        .buffered(args.concurrency.get())
+        .enumerate();
+
+    // Every completed chunk has already been written so stopping here leaves a
+    // file that can be resumed.
+    while let Some((index, (start, end, readings))) = tokio::select! {
+        next = chunks.next() => next,
+        _ = &mut interrupt => {
+            eprintln!("\nInterrupted, use --resume to continue.");
+            None
+        }
+    } {
+        eprint!("\rFetched {} of {} ({})", index + 1, count, start.date());
+        let readings = readings?;
+
+        for reading in readings {
+            writeln!(
+                file,
+                "{},{}",
+                reading.start.format(&Rfc3339).str_err()?,
+                reading.value
+            )
+            .str_err()?;
+        }
+        file.flush().str_err()?;
+
+        if let Some(ref path) = args.state {
+            let next = Reading::new(end, period, 0.0).end();
+            backfill_state::store(
+                path,
+                &backfill_state::BackfillState::new(&resource.id, period, &args.output, next),
+            )?;
         }
     }
+    file.flush().str_err()?;
+    eprintln!();
 
-    if let (Some(username), Some(password)) = (&args.username, &args.password) {
-        GlowmarktApi::authenticate(username, password)
-            .await
-            .str_err()
+    Ok(())
+}
+
+/// Stores the token in the cache unless caching is disabled.
+fn cache_token(no_cache: bool, base_url: &str, username: &str, token: &str) {
+    if !no_cache {
+        if let Err(e) = token_cache::store(base_url, username, token) {
+            log::warn!("Failed to cache the token: {}", e);
+        }
+    }
+}
+
+/// Creates the API from a token or by authenticating, along with the username
+/// when authenticating so a replacement token can be cached.
+async fn login(args: &Args) -> Result<(GlowmarktApi, Option<String>), Failure> {
+    let endpoint = GlowmarktEndpoint::default()
+        .with_base_url(&args.base_url)
+        .with_app_id(&args.app_id);
+    let mut endpoint = if args.rate_limit > 0.0 {
+        endpoint.with_rate_limit(args.rate_limit)
     } else {
-        Err("Must pass username and password.".to_string())
+        endpoint.without_rate_limit()
+    };
+    if let Some(seconds) = args.time_budget {
+        endpoint = endpoint.with_time_budget(std::time::Duration::from_secs(seconds));
     }
+
+    let token = args.token.clone().or_else(|| {
+        if args.no_cache {
+            None
+        } else {
+            token_cache::load(&args.base_url, args.username.as_deref())
+        }
+    });
+
+    if let Some(ref token) = token {
+        let api = GlowmarktApi::with_endpoint(endpoint.clone(), token);
+
+        // No need to ask the API about a token that has clearly expired.
+        let expired = api
+            .expiry_from_token()
+            .is_some_and(|expiry| expiry <= OffsetDateTime::now_utc());
+
+        if !expired {
+            match api.validate().await {
+                Ok(_) => {
+                    return Ok((api, None));
+                }
+                Err(e) => {
+                    if e.kind != ErrorKind::NotAuthenticated {
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+    }
+
+    let (username, password) = match (&args.username, &args.password) {
+        (Some(username), Some(password)) => (username.clone(), password.clone()),
+        (username, _) if io::stdin().is_terminal() => prompt_credentials(username.clone())?,
+        _ => {
+            return Err(Failure::usage(
+                "Must pass username and password.".to_string(),
+            ))
+        }
+    };
+
+    let api = GlowmarktApi::auth(endpoint, &username, &password).await?;
+    cache_token(args.no_cache, &args.base_url, &username, &api.token());
+
+    Ok((api, Some(username)))
 }
 
-#[tokio::main]
-async fn main() -> Result<(), String> {
+/// Asks the user for whichever credentials were not provided.
+fn prompt_credentials(username: Option<String>) -> Result<(String, String), String> {
+    let username = match username {
+        Some(username) => username,
+        None => {
+            eprint!("Username: ");
+            io::stderr().flush().str_err()?;
+
+            let mut username = String::new();
+            io::stdin().read_line(&mut username).str_err()?;
+            username.trim().to_string()
+        }
+    };
+
+    let password = rpassword::prompt_password("Password: ").str_err()?;
+
+    Ok((username, password))
+}
+
+async fn run() -> Result<(), Failure> {
     if let Err(e) = Logger::try_with_env_or_str("info").and_then(|logger| logger.start()) {
         eprintln!("Warning, failed to start logging: {}", e);
     }
 
     let args = Args::parse();
 
-    let api = login(&args).await?;
+    if let Command::Logout = args.command {
+        return Ok(token_cache::clear()?);
+    }
+
+    let (api, username) = login(&args).await?;
+    let options = args.options;
+
+    // A clone shares the token, which is replaced should it expire while
+    // running the command.
+    let session = api.clone();
+    let token = api.token();
 
-    match args.command {
-        Command::Token => {
-            println!("{}", api.token);
+    let result = match args.command {
+        Command::Token { json: false } => {
+            println!("{}", api.token());
+            Ok(())
+        }
+        Command::Token { json: true } => {
+            let info = TokenInfo {
+                token: api.token(),
+                expiry: api.expiry().or_else(|| api.expiry_from_token()),
+                account_id: api.account_id().map(str::to_owned),
+            };
+
+            println!("{}", options.to_json(&info)?);
+            Ok(())
+        }
+        Command::Ping => {
+            api.ping().await?;
+            match api.expiry() {
+                Some(expiry) => println!(
+                    "OK, token valid until {}",
+                    expiry.format(&Rfc3339).str_err()?
+                ),
+                None => println!("OK"),
+            }
             Ok(())
         }
-        Command::Device { id } => display_result(api.devices().await, id),
-        Command::DeviceType { id } => display_result(api.device_types().await, id),
-        Command::ResourceType { id } => display_result(api.resource_types().await, id),
-        Command::Resource { id } => display_result(api.resources().await, id),
+        Command::Logout => unreachable!(),
+        Command::Device { id } => display_result(api.devices().await, id, "device", options),
+        Command::DeviceType { id: Some(id) } => match api.device_type(&id).await {
+            Ok(device_type) => {
+                println!("{}", options.to_json(&device_type)?);
+                Ok(())
+            }
+            Err(error) if error.kind == ErrorKind::NotFound => {
+                Err(Failure::no_data(format!("Unknown device type {}", id)))
+            }
+            Err(error) => Err(error.into()),
+        },
+        Command::DeviceType { id: None } => {
+            display_result(api.device_types().await, None, "device type", options)
+        }
+        Command::ResourceType { id: Some(id) } => match api.resource_type(&id).await {
+            Ok(resource_type) => {
+                println!("{}", options.to_json(&resource_type)?);
+                Ok(())
+            }
+            Err(error) if error.kind == ErrorKind::NotFound => {
+                Err(Failure::no_data(format!("Unknown resource type {}", id)))
+            }
+            Err(error) => Err(error.into()),
+        },
+        Command::ResourceType { id: None } => {
+            display_result(api.resource_types().await, None, "resource type", options)
+        }
+        Command::Resource { id } => display_result(api.resources().await, id, "resource", options),
+        Command::VirtualEntity { id } => {
+            display_result(api.virtual_entities().await, id, "virtual entity", options)
+        }
+        Command::Tree { json } => tree(api, json, options).await,
         Command::Readings {
             resource_id,
-            from,
-            to,
-        } => readings(api, resource_id, from, to).await,
-        Command::Tariff { resource_id } => latest_tariff(api, resource_id).await,
-        Command::TariffList { resource_id } => tariff_list(api, resource_id).await,
-        Command::Influx {
-            device,
-            no_strip,
-            tags,
-            from,
-            to,
-        } => influx(api, device, no_strip, tags.into_iter().collect(), from, to).await,
+            range,
+            format,
+        } => readings(api, resource_id, range, format, options).await,
+        Command::Summary { resource_id, range } => summary(api, resource_id, range, options).await,
+        Command::Cost { resource_id, range } => cost(api, resource_id, range, options).await,
+        Command::Tariff { resource_id } => latest_tariff(api, resource_id, options).await,
+        Command::TariffList { resource_id } => tariff_list(api, resource_id, options).await,
+        Command::Influx(influx_args) => influx(api, influx_args, options).await,
+        Command::Backfill(backfill_args) => backfill(api, backfill_args, options).await,
+    };
+
+    if let Some(username) = username {
+        if session.token() != token {
+            cache_token(args.no_cache, &args.base_url, &username, &session.token());
+        }
+    }
+
+    result
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(failure) = run().await {
+        eprintln!("Error: {}", failure.message);
+        process::exit(failure.code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::format_description::well_known::Rfc3339;
+
+    use super::*;
+
+    fn at(value: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(value, &Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn relative_range_from_fixed_now() {
+        let range = DateRange {
+            from: "-1440".to_string(),
+            to: None,
+        };
+
+        assert_eq!(
+            range
+                .parse_at(
+                    UtcOffset::UTC,
+                    ReadingPeriod::HalfHour,
+                    at("2024-03-02T12:00:00Z")
+                )
+                .unwrap(),
+            (at("2024-03-01T12:00:00Z"), at("2024-03-02T12:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn future_dates_are_rejected() {
+        let range = DateRange {
+            from: "2024-03-03".to_string(),
+            to: None,
+        };
+
+        assert!(range
+            .parse_at(
+                UtcOffset::UTC,
+                ReadingPeriod::HalfHour,
+                at("2024-03-02T12:00:00Z")
+            )
+            .is_err());
     }
 }
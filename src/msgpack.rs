@@ -0,0 +1,106 @@
+//! Storing readings in a local file as MessagePack.
+//!
+//! This is far smaller and quicker to load than JSON for long runs of
+//! half-hourly readings. Only available with the `msgpack` feature.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{Error, ErrorKind, Reading, ReadingPeriod};
+
+/// A reading as stored in the file, including its period.
+#[derive(Serialize, Deserialize)]
+struct CachedReading {
+    #[serde(with = "time::serde::rfc3339")]
+    start: OffsetDateTime,
+    /// Missing from files written before the period was stored.
+    #[serde(default)]
+    period: ReadingPeriod,
+    value: f32,
+}
+
+impl From<&Reading> for CachedReading {
+    fn from(reading: &Reading) -> Self {
+        Self {
+            start: reading.start,
+            period: reading.period,
+            value: reading.value,
+        }
+    }
+}
+
+impl From<CachedReading> for Reading {
+    fn from(reading: CachedReading) -> Self {
+        Reading::new(reading.start, reading.period, reading.value)
+    }
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> Error {
+    Error {
+        kind: ErrorKind::Client,
+        message: format!("Failed to access {}: {}", path.display(), error),
+        source: Some(Box::new(error)),
+    }
+}
+
+/// Writes readings to a file, replacing any existing content.
+///
+/// Unlike JSON the period of each reading is stored as well.
+pub fn cache_readings<P: AsRef<Path>>(path: P, readings: &[Reading]) -> Result<(), Error> {
+    let path = path.as_ref();
+    let mut writer = BufWriter::new(File::create(path).map_err(|e| io_error(path, e))?);
+
+    // Fields are written with their names so that fields added later can be
+    // defaulted when reading back older files.
+    let readings: Vec<CachedReading> = readings.iter().map(CachedReading::from).collect();
+    rmp_serde::encode::write_named(&mut writer, &readings).map_err(|e| Error {
+        kind: ErrorKind::Client,
+        message: format!("Failed to encode readings: {}", e),
+        source: Some(Box::new(e)),
+    })?;
+
+    writer.flush().map_err(|e| io_error(path, e))
+}
+
+/// Reads readings previously written by [`cache_readings`].
+pub fn load_cached_readings<P: AsRef<Path>>(path: P) -> Result<Vec<Reading>, Error> {
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path).map_err(|e| io_error(path, e))?);
+
+    let readings: Vec<CachedReading> = rmp_serde::from_read(reader).map_err(|e| Error {
+        kind: ErrorKind::Response,
+        message: format!("Failed to decode readings from {}: {}", path.display(), e),
+        source: Some(Box::new(e)),
+    })?;
+
+    Ok(readings.into_iter().map(Reading::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use time::format_description::well_known::Rfc3339;
+
+    use super::*;
+
+    #[test]
+    fn period_survives_the_cache() {
+        let start = OffsetDateTime::parse("2024-01-01T00:00:00+01:00", &Rfc3339).unwrap();
+        let readings = vec![
+            Reading::new(start, ReadingPeriod::Month, 310.5),
+            Reading::new(start, ReadingPeriod::Day, 10.25),
+        ];
+
+        let path = std::env::temp_dir().join(format!("glowmarkt-{}.msgpack", std::process::id()));
+        cache_readings(&path, &readings).unwrap();
+        let loaded = load_cached_readings(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), readings);
+    }
+}
@@ -0,0 +1,61 @@
+//! A token bucket limiting the rate of requests to the API.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter {
+    per_second: f64,
+    capacity: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(per_second: f64) -> Self {
+        let capacity = per_second.max(1.0);
+
+        Self {
+            per_second,
+            capacity,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            })),
+        }
+    }
+
+    /// Waits until a request may be sent.
+    ///
+    /// A token is reserved immediately, even if that leaves the bucket in
+    /// debt, so waiting requests are released in the order they arrived.
+    pub(crate) async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.per_second;
+            bucket.tokens = (bucket.tokens + refill).min(self.capacity);
+            bucket.refilled = now;
+
+            bucket.tokens -= 1.0;
+            if bucket.tokens >= 0.0 {
+                None
+            } else {
+                Some(Duration::from_secs_f64(-bucket.tokens / self.per_second))
+            }
+        };
+
+        if let Some(wait) = wait {
+            log::trace!("Rate limited, waiting {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
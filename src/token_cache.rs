@@ -0,0 +1,78 @@
+//! Persists the authentication token between invocations of the CLI.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ErrorStr;
+
+#[derive(Serialize, Deserialize)]
+struct CachedToken {
+    base_url: String,
+    username: String,
+    token: String,
+}
+
+fn path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("glowmarkt").join("token.json"))
+}
+
+/// Loads the cached token for the endpoint, if it was generated for the user.
+///
+/// When no username is given any token for the endpoint is used.
+pub fn load(base_url: &str, username: Option<&str>) -> Option<String> {
+    let data = fs::read_to_string(path()?).ok()?;
+    let cached: CachedToken = serde_json::from_str(&data).ok()?;
+
+    if cached.base_url == base_url && username.is_none_or(|u| u == cached.username) {
+        Some(cached.token)
+    } else {
+        None
+    }
+}
+
+/// Stores the token so only the current user can read it.
+pub fn store(base_url: &str, username: &str, token: &str) -> Result<(), String> {
+    let path = path().ok_or_else(|| "No cache directory available".to_string())?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).str_err()?;
+    }
+
+    let data = serde_json::to_string(&CachedToken {
+        base_url: base_url.to_owned(),
+        username: username.to_owned(),
+        token: token.to_owned(),
+    })
+    .str_err()?;
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&path).str_err()?;
+    #[cfg(unix)]
+    {
+        // The mode above only applies when the file is created.
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .str_err()?;
+    }
+
+    file.write_all(data.as_bytes()).str_err()
+}
+
+/// Removes the cached token, if there is one.
+pub fn clear() -> Result<(), String> {
+    match path() {
+        Some(path) if path.exists() => fs::remove_file(path).str_err(),
+        _ => Ok(()),
+    }
+}